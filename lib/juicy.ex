@@ -4,7 +4,7 @@ defmodule Juicy do
   """
 
   @type ejson :: any
-  @type error :: any
+  @type error :: Juicy.Error.t | any
 
   @doc """
   The simplest and most basic parse function.
@@ -13,25 +13,216 @@ defmodule Juicy do
   errors in the input.
 
   This is the fastest parse function, as it does not need to walk a spec tree.
+
+  ## Options
+
+    * `:duplicate_keys` - what to do when an object contains the same key
+      more than once. `:last` keeps the last value, `:first` keeps the first
+      one and `:error` fails the parse with a `:duplicate_key` error pointing
+      at the repeated key. Defaults to `:last`. Spec nodes accept the same
+      option, it applies to the node and everything below it.
+    * `:keys` - how object keys are decoded. Defaults to `:strings`.
+      * `:strings` - keys are binaries.
+      * `:atoms` - keys are converted to atoms. Only use this on trusted
+        input, atoms are never garbage collected.
+      * `:existing_atoms` - keys become atoms only if the atom already
+        exists, other keys stay binaries. Use `{:existing_atoms, :error}`
+        to fail with a `:missing_atom` error instead.
+      * `{:intern, atoms}` - keys matching one of the given atoms become
+        that atom, other keys stay binaries.
+
+      Keys that are not plain ASCII, or are longer than 255 bytes, always
+      stay binaries.
+    * `:objects` - what objects are decoded into. Defaults to `:maps`.
+      * `:maps` - maps, the order of keys is lost.
+      * `:ordered_lists` - lists of `{key, value}` tuples in document order.
+      * `:proplists` - lists of `{key, value}` tuples with the last key in
+        the document first, so a lookup finds the same value a map would
+        keep. Slightly cheaper than `:ordered_lists`.
+
+      With list objects and `duplicate_keys: :last` every pair is kept.
+      Spec nodes accept the same option, it applies to the node and
+      everything below it. Nodes with a `struct_atom` are always maps.
+    * `:floats` - what numbers with a fraction or an exponent are decoded
+      into. Defaults to `:native`.
+      * `:native` - floats. Precision beyond what a double can hold is lost.
+      * `:decimal` - `%Decimal{}` structs, built directly from the digits so
+        that no precision is lost. Juicy does not depend on `Decimal`, add it
+        to your own dependencies.
+      * `:string` - the number as a binary, such as `"1.50e-3"`. A `+` in
+        the exponent is dropped and `E` becomes `e`.
+
+      Spec nodes accept the same option, it applies to the node and
+      everything below it. Integers are always decoded into integers.
+
+  ### Limits
+
+  These protect against hostile documents that would use an unreasonable
+  amount of memory. Each one is unlimited by default and fails the parse
+  with its own error reason, for example `:max_depth_exceeded`.
+
+    * `:max_depth` - how deeply arrays and objects may be nested.
+    * `:max_string_bytes` - the length of a string or object key, in bytes
+      after unescaping.
+    * `:max_number_digits` - the number of digits in a number, counting the
+      integer, fraction and exponent parts.
+    * `:max_keys_per_object` - the number of keys in a single object,
+      counting repeated keys.
+    * `:max_total_bytes` - the size of the whole input.
+  """
+  @spec parse(binary, Keyword.t) :: {:ok, ejson} | {:error, error}
+  def parse(binary, opts \\ []) do
+    Juicy.Basic.parse(binary, opts)
+  end
+
+  @doc """
+  Parses the input binary guided by a spec.
+
+  Takes the same options as `parse/2`, except for `:keys`. `:duplicate_keys`
+  and `:objects` apply to nodes that do not set them.
+  """
+  @spec parse_spec(binary, Juicy.Spec.t, Keyword.t) :: {:ok, any} | {:error, error}
+  def parse_spec(binary, spec, opts \\ []) do
+    Juicy.Basic.parse_spec(binary, spec, opts)
+  end
+
+  @doc """
+  Parses a stream of binaries guided by a spec, yielding the values of
+  streamed nodes as they complete.
+
+  Takes the same options as `parse_spec/3`.
+  """
+  @spec parse_stream(Stream.t, Juicy.Spec.t, Keyword.t) :: Stream.t
+  def parse_stream(stream, spec, opts \\ []) do
+    Juicy.Stream.stream(stream, spec, opts)
+  end
+
+  @doc """
+  Parses a stream of binaries holding one document per line, as in NDJSON
+  and JSON Lines, guided by a spec.
+
+  Each document is emitted as `{:line, line, value}` once it ends, with
+  `line` the 1-based line it is on. Values of streamed nodes inside it are
+  yielded before it, as with `parse_stream/3`. Blank lines are skipped and
+  the last line does not need to end with a newline.
+
+  A line that fails emits `{:error, %Juicy.Error{}}`, whose `:line` is
+  that of the failing line, and ends the stream. With `skip_invalid: true`
+  parsing instead carries on with the next line, except after
+  `:max_total_bytes_exceeded`, as that limit is on the whole stream.
+  `:finished` is emitted once all of the input has been parsed.
+
+  Takes the same options as `parse_stream/3`, plus `:skip_invalid`.
   """
-  @spec parse(binary) :: {:ok, ejson} | {:error, error}
-  def parse(binary) do
-    Juicy.Basic.parse(binary)
+  @spec parse_lines(Stream.t, Juicy.Spec.t, Keyword.t) :: Stream.t
+  def parse_lines(stream, spec \\ {:any, []}, opts \\ []) do
+    Juicy.Stream.stream(stream, spec, [documents: :lines] ++ opts)
   end
 
-  @spec parse_spec(binary, Juicy.Spec.t) :: {:ok, any} | {:error, error}
-  def parse_spec(binary, spec) do
-    Juicy.Basic.parse_spec(binary, spec)
+  @doc """
+  Parses a binary holding several documents into a list of their values.
+
+  ## Options
+
+    * `:format` - how the documents are laid out. Defaults to
+      `:concatenated`.
+      * `:concatenated` - one after the other, optionally separated by
+        whitespace, as in `{}{}[]`.
+      * `:json_seq` - a JSON text sequence, RFC 7464, where each document
+        is preceded by a `"\\x1E"` record separator and followed by a
+        newline.
+
+  A document that is cut off by the end of the input, or of its record,
+  fails with a `:truncated` error. In a JSON text sequence, this includes a
+  last record that is not followed by a newline.
+
+  Takes the same options as `parse/2`.
+  """
+  @spec parse_many(binary, Keyword.t) :: {:ok, [ejson]} | {:error, error}
+  def parse_many(binary, opts \\ []) do
+    [binary]
+    |> parse_many_stream({:any, []}, opts)
+    |> Enum.reduce_while([], fn
+      {:document, _index, value}, values -> {:cont, [value | values]}
+      :finished, values -> {:halt, {:ok, Enum.reverse(values)}}
+      {:error, error}, _values -> {:halt, {:error, error}}
+    end)
   end
 
-  @spec parse_stream(Stream.t, Juicy.Spec.t) :: Stream.t
-  def parse_stream(stream, spec) do
-    Juicy.Stream.stream(stream, spec)
+  @doc """
+  Parses a stream of binaries holding several documents, guided by a spec.
+
+  Each document is emitted as `{:document, index, value}` once it ends,
+  with `index` counting from 0. Values of streamed nodes inside it are
+  yielded before it, as with `parse_stream/3`. `:finished` is emitted once
+  all of the input has been parsed, and a document that fails emits
+  `{:error, %Juicy.Error{}}` and ends the stream.
+
+  With `format: :json_seq` and `skip_invalid: true`, parsing carries on
+  with the next record after one fails, as with `parse_lines/3`. Its index
+  is still counted.
+
+  Takes the same options as `parse_many/2` and `parse_stream/3`.
+  """
+  @spec parse_many_stream(Stream.t, Juicy.Spec.t, Keyword.t) :: Stream.t
+  def parse_many_stream(stream, spec \\ {:any, []}, opts \\ []) do
+    {format, opts} = Keyword.pop(opts, :format, :concatenated)
+    Juicy.Stream.stream(stream, spec, [documents: format] ++ opts)
   end
 
-  @spec validate_spec(Juicy.Spec.t) :: boolean
+  @doc """
+  Encodes a term into a JSON binary.
+
+  Maps, lists, binaries, atoms, integers (including bignums) and floats are
+  supported. `true`, `false` and `nil` become their JSON counterparts, other
+  atoms are encoded as strings. Structs are not supported and fail with
+  `{:unsupported_type, struct}`.
+
+  ## Options
+
+    * `:pretty` - when `true`, the output is indented and object keys are
+      sorted, so the same input always produces the same bytes. Defaults to
+      `false`.
+    * `:indent` - the indentation used per nesting level when pretty
+      printing. Either a number of spaces or a binary such as `"\t"`.
+      Defaults to `2`.
+    * `:line_separator` - inserted before every element when pretty
+      printing. Defaults to `"\n"`.
+    * `:after_colon` - inserted between an object key and its value when
+      pretty printing. Defaults to `" "`.
+    * `:canonical` - when `true`, the output follows RFC 8785 (JSON
+      Canonicalization Scheme): keys are sorted by their UTF-16 code units,
+      floats use the shortest ECMAScript representation and there is no
+      whitespace. Keys that serialize to the same string are an error, as
+      are integers beyond 2^53 - 1 in either direction, which a double can
+      not hold exactly. Cannot be combined with `:pretty`.
+  """
+  @spec encode(any, Keyword.t) :: {:ok, binary} | {:error, error}
+  def encode(term, opts \\ []) do
+    Juicy.Encoder.encode(term, opts)
+  end
+
+  @doc """
+  Reads a spec ahead of time. The returned handle can be passed to the
+  parse functions in place of the spec, which saves reading the spec on
+  every parse. Results are the same either way.
+
+  The spec is read with the default parse options. Parses that change
+  `:duplicate_keys`, `:objects` or `:floats` read it again, since nodes
+  inherit those.
+  """
+  @spec compile_spec(Juicy.Spec.t) :: {:ok, Juicy.Spec.compiled} | :error
+  def compile_spec(spec) do
+    Juicy.Native.compile_spec(spec)
+  end
+
+  @doc """
+  Checks that a spec is valid, returning it compiled as `compile_spec/1`
+  does.
+  """
+  @spec validate_spec(Juicy.Spec.t) :: {:ok, Juicy.Spec.compiled} | :error
   def validate_spec(spec) do
-    Juicy.Native.validate_spec(spec)
+    compile_spec(spec)
   end
 
 end
@@ -0,0 +1,13 @@
+defmodule Juicy.Encoder do
+  @moduledoc false
+
+  def encode(term, opts) do
+    handle_encode_return(Juicy.Native.encode_init(term, opts))
+  end
+
+  defp handle_encode_return({:iter, stack, res}) do
+    handle_encode_return(Juicy.Native.encode_iter(stack, res))
+  end
+  defp handle_encode_return(ret), do: ret
+
+end
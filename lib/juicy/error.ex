@@ -0,0 +1,30 @@
+defmodule Juicy.Error do
+  @moduledoc """
+  Describes why and where parsing a document failed.
+
+    * `:reason` - a machine readable atom describing the error.
+    * `:message` - a human readable explanation.
+    * `:offset` - byte offset of the error in the input.
+    * `:line`, `:column` - 1-based position of the error. The column is
+      counted in bytes.
+    * `:path` - JSON Pointer of the value enclosing the error.
+    * `:snippet` - a few bytes of input around the error.
+    * `:details` - for errors that group several problems, such as
+      `:missing_keys`, a map with the `:reason`, `:message` and `:path` of
+      each one. Empty otherwise.
+  """
+
+  defstruct reason: nil, message: nil, offset: nil, line: nil, column: nil, path: "", snippet: "", details: []
+
+  @type t :: %__MODULE__{
+    reason: atom,
+    message: String.t,
+    offset: non_neg_integer,
+    line: pos_integer,
+    column: pos_integer,
+    path: String.t,
+    snippet: String.t,
+    details: [%{reason: atom, message: String.t, path: String.t}],
+  }
+
+end
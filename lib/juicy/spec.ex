@@ -2,4 +2,34 @@ defmodule Juicy.Spec do
 
   @type t :: any
 
+  @typedoc "A spec read ahead of time by `Juicy.compile_spec/1`."
+  @type compiled :: reference
+
+  @type schema_error ::
+    {:unsupported_keyword | :invalid_keyword, String.t, String.t}
+    | {:invalid_schema, nil, String.t}
+
+  @doc """
+  Builds a spec from a JSON Schema, given as decoded JSON with binary keys.
+  It is returned compiled, as with `Juicy.compile_spec/1`.
+
+  Supports a subset of draft 2020-12: `type`, `properties`, `required`,
+  `items`, `enum`, `const`, `minimum`, `maximum`, `minLength`, `maxLength`,
+  `pattern`, `format` (`email`, `uuid` and `date-time`), and `oneOf` with a
+  `discriminator` whose `propertyName` each alternative fixes with `const`
+  or `enum`. Values of `enum` and `const` must be strings or `null`.
+  Annotations such as `title` and `description` are ignored.
+
+  Any other keyword is reported with a JSON pointer to it in the schema,
+  as in `{:unsupported_keyword, "$ref", "/properties/owner/$ref"}`.
+
+  Values that fail `type`, `enum`, `const` or a bound are reported together
+  as a `:constraint_violations` error. A value of a type the schema does not
+  allow is reported as `:type_mismatch`.
+  """
+  @spec from_json_schema(map | boolean) :: {:ok, compiled} | {:error, schema_error}
+  def from_json_schema(schema) do
+    Juicy.Native.compile_json_schema(schema)
+  end
+
 end
@@ -1,9 +1,9 @@
 defmodule Juicy.Stream do
   @moduledoc false
 
-  defstruct reader: nil, spec: nil, binaries: nil, parser: nil, output_queue: [], state: {:read_input, :parsing_not_done, nil}
+  defstruct reader: nil, spec: nil, opts: [], binaries: nil, parser: nil, output_queue: [], state: {:read_input, :parsing_not_done, nil}
 
-  def stream(input, spec) do
+  def stream(input, spec, opts) do
     reader = input
     |> Stream.transform(0, fn(elem, pos) -> {[{pos, elem}], pos+byte_size(elem)} end)
     |> stream_take_init
@@ -11,6 +11,7 @@ defmodule Juicy.Stream do
     %__MODULE__{
       reader: reader,
       spec: spec,
+      opts: opts,
     }
   end
 
@@ -38,7 +39,7 @@ defimpl Enumerable, for: Juicy.Stream do
   end
 
   def reduce(js = %Juicy.Stream{}, acc, fun) do
-    {:ok, parser} = Juicy.Native.stream_parse_init(js.spec)
+    {:ok, parser} = Juicy.Native.stream_parse_init(js.spec, js.opts)
     js = %Juicy.Stream{ js |
             parser: parser,
             binaries: [],
@@ -68,21 +69,24 @@ defimpl Enumerable, for: Juicy.Stream do
                     }
               {:loop, js}
             {:halted, _} ->
-              js = %{js |
-                     state: {:emit_items, :parsing_done, nil},
-                     output_queue: [{:error, :early_eoi}],
-                    }
-              {:loop, js}
+              case Keyword.get(js.opts, :documents, :single) do
+                :single ->
+                  js = %{js |
+                         state: {:emit_items, :parsing_done, nil},
+                         output_queue: [{:error, :early_eoi}],
+                        }
+                  {:loop, js}
+                _ ->
+                  js.binaries
+                  |> Juicy.Native.stream_parse_end(js.parser)
+                  |> parsed(js)
+              end
           end
 
         {:parse, :parsing_not_done, _} ->
-          {status, yields, binaries, state} = Juicy.Native.stream_parse_iter(js.binaries, js.parser)
-          js = %{js | output_queue: yields, parser: state, binaries: binaries}
-          case status do
-            :finished -> {:loop, %{js | state: {:emit_items, :parsing_done, nil}}}
-            :iter -> {:loop, %{js | state: {:emit_items, :parsing_not_done, nil}}}
-            :await_input -> {:loop, %{js | state: {:emit_items, :parsing_not_done, :await_input}}}
-          end
+          js.binaries
+          |> Juicy.Native.stream_parse_iter(js.parser)
+          |> parsed(js)
 
         {:emit_items, :parsing_done, _} ->
           case js.output_queue do
@@ -114,4 +118,13 @@ defimpl Enumerable, for: Juicy.Stream do
     end
   end
 
+  defp parsed({status, yields, binaries, state}, js) do
+    js = %{js | output_queue: yields, parser: state, binaries: binaries}
+    case status do
+      :finished -> {:loop, %{js | state: {:emit_items, :parsing_done, nil}}}
+      :iter -> {:loop, %{js | state: {:emit_items, :parsing_not_done, nil}}}
+      :await_input -> {:loop, %{js | state: {:emit_items, :parsing_not_done, :await_input}}}
+    end
+  end
+
 end
@@ -5,16 +5,17 @@ use iterative_json_parser::{
 
 use rustler::resource::ResourceArc;
 use rustler::types::binary::Binary;
-use rustler::types::binary::OwnedBinary;
-use rustler::types::map::map_new;
 use rustler::{Encoder, Env, NifResult, Term};
 
+use errors::{push_pointer_index, push_pointer_key, DecodeError, ErrorReason, Location};
 use input_provider::single::SingleBinaryProvider;
 use input_provider::InputProvider;
-use numbers::number_data_to_term;
-use strings::BuildString;
+use numbers::{number_data_to_term, number_digits};
+use objects::{finish, insert, key_atom, key_bytes, new_object, InsertError, OpenObject};
+use options::{read_parse_opts, Keys, Limit, ParseOptions};
+use strings::{binary_from_slice, BuildString};
+use tree_spec::ValueType;
 
-use std::io::Write;
 use std::ops::DerefMut;
 use std::sync::Mutex;
 
@@ -28,6 +29,43 @@ struct BasicSS<'a, 'b> {
 
     out_stack: Vec<Term<'a>>,
     current_string: &'b mut BuildString,
+    /// Kind of every container in `out_stack`, used to work out the
+    /// path when reporting errors.
+    containers: &'b mut Vec<ValueType>,
+    /// One for every object in `out_stack`.
+    objects: &'b mut Vec<OpenObject>,
+
+    options: &'b ParseOptions,
+
+    /// Set when the sink fails. The parser is stopped with a bail at the
+    /// next opportunity.
+    error: Option<DecodeError>,
+}
+
+impl<'a, 'b> BasicSS<'a, 'b> {
+    /// The path is filled in from the stack once the parser has stopped.
+    fn fail(&mut self, message: &str) -> Result<(), ()> {
+        self.fail_with(ErrorReason::Internal(message.to_string()))
+    }
+
+    fn fail_with(&mut self, reason: ErrorReason) -> Result<(), ()> {
+        if self.error.is_none() {
+            self.error = Some(DecodeError::new(reason, self.position, String::new()));
+        }
+        Err(())
+    }
+
+    fn check_limit(&mut self, result: Result<(), Limit>) -> Result<(), ()> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(limit) => self.fail_with(ErrorReason::LimitExceeded(limit)),
+        }
+    }
+
+    fn check_string_len(&mut self) {
+        let result = self.options.limits.string_bytes(self.current_string.len());
+        let _ = self.check_limit(result);
+    }
 }
 
 impl<'a, 'b> Bailable for BasicSS<'a, 'b> {
@@ -41,10 +79,14 @@ impl<'a, 'b> Source for BasicSS<'a, 'b> {
         self.position += num
     }
     fn peek_char(&mut self) -> PeekResult<()> {
-        if self.position == self.next_reschedule {
+        if self.position == self.next_reschedule || self.error.is_some() {
             PeekResult::Bail(())
         } else if let Some(character) = self.input.byte(self.position) {
-            PeekResult::Ok(character)
+            let result = self.options.limits.total_bytes(self.position);
+            match self.check_limit(result) {
+                Ok(()) => PeekResult::Ok(character),
+                Err(()) => PeekResult::Bail(()),
+            }
         } else {
             PeekResult::Eof
         }
@@ -62,14 +104,28 @@ impl<'a, 'b> Source for BasicSS<'a, 'b> {
 
 impl<'a, 'b> Sink for BasicSS<'a, 'b> {
     fn push_map(&mut self, _pos: Position) {
-        self.out_stack.push(map_new(self.env));
+        self.out_stack
+            .push(new_object(self.env, self.options.objects));
+        self.containers.push(ValueType::Object);
+        self.objects.push(OpenObject::default());
+
+        let result = self.options.limits.depth(self.containers.len());
+        let _ = self.check_limit(result);
     }
     fn push_array(&mut self, _pos: Position) {
         let arr: Vec<Term> = Vec::new();
         self.out_stack.push(arr.encode(self.env));
+        self.containers.push(ValueType::Array);
+
+        let result = self.options.limits.depth(self.containers.len());
+        let _ = self.check_limit(result);
     }
     fn push_number(&mut self, _pos: Position, num: NumberData) -> Result<(), Self::Bail> {
-        let term = number_data_to_term(self.env, num, |r, b| {
+        let result = self.options.limits.number_digits(number_digits(&num));
+        self.check_limit(result)?;
+
+        let floats = self.options.floats;
+        let term = number_data_to_term(self.env, num, floats, |r, b| {
             self.input.push_range(r, b);
         });
         self.out_stack.push(term);
@@ -84,55 +140,128 @@ impl<'a, 'b> Sink for BasicSS<'a, 'b> {
         Ok(())
     }
 
-    fn start_string(&mut self, _pos: StringPosition) {
-        *self.current_string = BuildString::new();
+    fn start_string(&mut self, pos: StringPosition) {
+        *self.current_string = match pos {
+            // The key bytes are needed to look up the atom.
+            StringPosition::MapKey if self.options.keys != Keys::Strings => {
+                BuildString::new_owned()
+            }
+            _ => BuildString::new(),
+        };
     }
     fn append_string_range(&mut self, range: Range) {
         let input = &self.input;
         self.current_string.append_range(range, |r, b| {
             input.push_range(r, b);
         });
+        self.check_string_len();
     }
     fn append_string_single(&mut self, character: u8) {
         let input = &self.input;
         self.current_string.append_single(character, |r, b| {
             input.push_range(r, b);
         });
+        self.check_string_len();
     }
     fn append_string_codepoint(&mut self, codepoint: char) {
         let input = &self.input;
         self.current_string.append_codepoint(codepoint, |r, b| {
             input.push_range(r, b);
         });
+        self.check_string_len();
     }
-    fn finalize_string(&mut self, _pos: StringPosition) -> Result<(), Self::Bail> {
+    fn finalize_string(&mut self, pos: StringPosition) -> Result<(), Self::Bail> {
+        let is_key = match pos {
+            StringPosition::MapKey => true,
+            _ => false,
+        };
+        if is_key && self.options.keys != Keys::Strings {
+            let key = ::std::mem::replace(self.current_string, BuildString::None).owned_to_vec();
+            let atom = key_atom(self.env, &key, &self.options.keys);
+            if let Ok(Some(atom)) = atom {
+                self.out_stack.push(atom.encode(self.env));
+                return Ok(());
+            }
+
+            // Also pushed on error, so that the key ends up in the path.
+            match binary_from_slice(self.env, &key) {
+                Some(term) => self.out_stack.push(term),
+                None => return self.fail("failed to allocate key"),
+            }
+            return match atom {
+                Err(reason) => self.fail_with(reason),
+                _ => Ok(()),
+            };
+        }
+
         let string_term = match *self.current_string {
-            BuildString::None => "".encode(self.env),
+            BuildString::None => Some("".encode(self.env)),
             BuildString::Range(range) => self.input.range_to_term(self.env, range),
-            BuildString::Owned(ref buf) => {
-                let mut bin = OwnedBinary::new(buf.len()).unwrap();
-                bin.as_mut_slice().write(buf).unwrap();
-                bin.release(self.env).encode(self.env)
-            }
+            BuildString::Owned(ref buf) => binary_from_slice(self.env, buf),
         };
         *self.current_string = BuildString::None;
-        self.out_stack.push(string_term);
-        Ok(())
+        match string_term {
+            Some(term) => {
+                self.out_stack.push(term);
+                Ok(())
+            }
+            None => self.fail("failed to allocate string"),
+        }
     }
 
     fn finalize_map(&mut self, _pos: Position) -> Result<(), Self::Bail> {
-        Ok(())
+        self.containers.pop();
+        self.objects.pop();
+        let term = self.out_stack.pop().unwrap();
+        match finish(term, self.options.objects) {
+            Some(object) => {
+                self.out_stack.push(object);
+                Ok(())
+            }
+            None => {
+                self.out_stack.push(term);
+                self.fail("failed to reverse object")
+            }
+        }
     }
     fn finalize_array(&mut self, _pos: Position) -> Result<(), Self::Bail> {
+        self.containers.pop();
         let term = self.out_stack.pop().unwrap();
-        self.out_stack.push(term.list_reverse().ok().unwrap());
-        Ok(())
+        match term.list_reverse() {
+            Ok(reversed) => {
+                self.out_stack.push(reversed);
+                Ok(())
+            }
+            Err(_) => {
+                self.out_stack.push(term);
+                self.fail("failed to reverse array")
+            }
+        }
     }
     fn pop_into_map(&mut self) {
         let value = self.out_stack.pop().unwrap();
         let key = self.out_stack.pop().unwrap();
         let map = self.out_stack.pop().unwrap();
-        self.out_stack.push(map.map_put(key, value).ok().unwrap());
+        let open = self.objects.last_mut().unwrap();
+        match insert(self.env, open, map, key, value, self.options.duplicate_keys) {
+            Ok(map) => {
+                self.out_stack.push(map);
+                let keys = self.objects.last().unwrap().keys;
+                let result = self.options.limits.keys_per_object(keys);
+                let _ = self.check_limit(result);
+            }
+            Err(InsertError::Duplicate) => {
+                // Leave the key on the stack so that it ends up in the path.
+                self.out_stack.push(map);
+                self.out_stack.push(key);
+                self.out_stack.push(value);
+                let _ = self.fail_with(ErrorReason::DuplicateKey(key_bytes(key)));
+            }
+            Err(InsertError::Failed) => {
+                self.out_stack.push(map);
+                let _ = self.fail("failed to insert into map");
+            }
+        }
     }
     fn pop_into_array(&mut self) {
         let value = self.out_stack.pop().unwrap();
@@ -141,21 +270,63 @@ impl<'a, 'b> Sink for BasicSS<'a, 'b> {
     }
 }
 
-fn format_unexpected<'a>(env: Env<'a>, parser: &Parser, pos: Pos, reason: Unexpected) -> Term<'a> {
-    let parser_state = format!("{:?}", parser).encode(env);
-    let position = pos.0 as u64;
-    let explaination = reason.explain().encode(env);
-    (
-        ::atoms::error(),
-        (::atoms::unexpected(), position, explaination, parser_state),
-    )
-        .encode(env)
+/// Works out the JSON Pointer of the value being parsed from the
+/// partially built terms on the stack.
+fn stack_pointer(stack: &[Term], containers: &[ValueType]) -> String {
+    let mut pointer = String::new();
+    let mut idx = 0;
+    for container in containers {
+        let term = match stack.get(idx) {
+            Some(term) => *term,
+            None => break,
+        };
+        idx += 1;
+
+        match *container {
+            ValueType::Object => match stack.get(idx) {
+                Some(key) => {
+                    push_pointer_key(&mut pointer, &key_bytes(*key));
+                    idx += 1;
+                }
+                None => break,
+            },
+            _ => push_pointer_index(&mut pointer, term.list_length().unwrap_or(0)),
+        }
+    }
+    pointer
+}
+
+fn format_error<'a>(
+    env: Env<'a>,
+    input: Binary<'a>,
+    stack: &[Term<'a>],
+    containers: &[ValueType],
+    mut error: DecodeError,
+) -> Term<'a> {
+    error.path = stack_pointer(stack, containers);
+    let location = Location::from_slice(input.as_slice(), error.position);
+    (::atoms::error(), error.to_term(env, &location)).encode(env)
+}
+
+fn format_unexpected<'a>(
+    env: Env<'a>,
+    input: Binary<'a>,
+    stack: &[Term<'a>],
+    containers: &[ValueType],
+    pos: Pos,
+    reason: Unexpected,
+) -> Term<'a> {
+    let error = DecodeError::new(ErrorReason::Unexpected(reason), pos.0, String::new());
+    format_error(env, input, stack, containers, error)
 }
 
 pub struct IterState {
     parser: Parser,
     source_pos: usize,
     sink_string_state: BuildString,
+    containers: Vec<ValueType>,
+    objects: Vec<OpenObject>,
+    options: ParseOptions,
 }
 pub struct IterStateWrapper(Mutex<IterState>);
 
@@ -172,6 +343,10 @@ fn parse_inner<'a>(
         next_reschedule: iter_state.source_pos + 40_000,
         out_stack: stack,
         current_string: &mut iter_state.sink_string_state,
+        containers: &mut iter_state.containers,
+        objects: &mut iter_state.objects,
+        options: &iter_state.options,
+        error: None,
     };
 
     let result = iter_state.parser.run(&mut ss);
@@ -182,21 +357,51 @@ fn parse_inner<'a>(
             let term = ss.out_stack.pop().unwrap();
             Ok((::atoms::ok(), term).encode(env))
         }
-        Err(ParseError::SourceBail(())) => Err(ss.out_stack),
-        Err(ParseError::Unexpected(pos, reason)) => {
-            Ok(format_unexpected(env, &iter_state.parser, pos, reason))
+        Err(ParseError::SourceBail(())) => match ss.error.take() {
+            Some(error) => Ok(format_error(
+                env,
+                input,
+                &ss.out_stack,
+                &ss.containers,
+                error,
+            )),
+            None => Err(ss.out_stack),
+        },
+        Err(ParseError::Unexpected(pos, reason)) => Ok(format_unexpected(
+            env,
+            input,
+            &ss.out_stack,
+            &ss.containers,
+            pos,
+            reason,
+        )),
+        Err(err) => {
+            let position = ss.position;
+            let error = ss.error.take().unwrap_or_else(|| {
+                DecodeError::internal(&format!("{:?}", err), position, String::new())
+            });
+            Ok(format_error(
+                env,
+                input,
+                &ss.out_stack,
+                &ss.containers,
+                error,
+            ))
         }
-        err => panic!("{:?}", err),
     }
 }
 
-pub fn parse<'a>(env: Env<'a>, input_term: Term<'a>) -> NifResult<Term<'a>> {
+pub fn parse<'a>(env: Env<'a>, input_term: Term<'a>, opts_term: Term<'a>) -> NifResult<Term<'a>> {
     let input: Binary = input_term.decode()?;
+    let options = read_parse_opts(opts_term)?;
 
     let mut iter_state = IterState {
         parser: Parser::new(),
         source_pos: 0,
         sink_string_state: BuildString::None,
+        containers: Vec::new(),
+        objects: Vec::new(),
+        options: options,
     };
 
     match parse_inner(env, input, vec![], &mut iter_state) {
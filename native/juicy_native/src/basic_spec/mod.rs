@@ -4,29 +4,44 @@ use rustler::resource::ResourceArc;
 use rustler::types::binary::Binary;
 use rustler::{Encoder, Env, NifResult, Term};
 
-use strings::BuildString;
+use errors::{DecodeError, ErrorReason, Location};
+use options::{read_parse_opts, Documents, ParseOptions};
 
-use tree_spec::spec_from_term;
+use tree_spec::read_spec;
 use tree_spec::SpecWalker;
 
-use input_provider::single::SingleBinaryProvider;
+use input_provider::streaming::StreamingInputProvider;
 
 use path_tracker::PathTracker;
+use spec_sink::{BailType, SSState, StreamingSS};
 
 use std::ops::DerefMut;
+use std::ops::Range;
 use std::sync::Mutex;
 
-mod source_sink;
-use self::source_sink::{SSState, StreamingSS};
-
-fn format_unexpected<'a>(env: Env<'a>, pos: Pos, reason: Unexpected) -> Term<'a> {
-    let position = pos.0 as u64;
-    let explaination = reason.explain().encode(env);
-    (
-        ::atoms::error(),
-        (::atoms::unexpected(), position, explaination),
-    )
-        .encode(env)
+fn format_error<'a>(env: Env<'a>, input: Binary<'a>, error: DecodeError) -> Term<'a> {
+    let location = Location::from_slice(input.as_slice(), error.position);
+    error.to_term(env, &location)
+}
+
+fn format_unexpected<'a>(
+    env: Env<'a>,
+    input: Binary<'a>,
+    path_tracker: &PathTracker,
+    pos: Pos,
+    reason: Unexpected,
+) -> Term<'a> {
+    let error = DecodeError::new(
+        ErrorReason::Unexpected(reason),
+        pos.0,
+        path_tracker.json_pointer(),
+    );
+    format_error(env, input, error)
+}
+
+/// Input for the sink that is all there in `binary`.
+fn whole(binary: Binary) -> [(Range<usize>, Binary); 1] {
+    [(0..binary.len(), binary)]
 }
 
 pub struct BasicSpecIterState {
@@ -39,20 +54,19 @@ pub fn parse_init<'a>(
     env: Env<'a>,
     binary_term: Term<'a>,
     spec_term: Term<'a>,
+    opts_term: Term<'a>,
 ) -> NifResult<Term<'a>> {
     let binary: Binary = binary_term.decode()?;
-    let spec = spec_from_term(spec_term)?;
-
-    let ss_state = SSState {
-        path_tracker: PathTracker {
-            path: Vec::new(),
-            walker: SpecWalker::new(spec),
-        },
-
-        position: 0,
-        first_needed: 0,
-        current_string: BuildString::None,
+    let mut options = read_parse_opts(opts_term)?;
+    // The binary holds a single document.
+    options.documents = Documents::Single;
+    let spec = read_spec(spec_term, &options)?;
+
+    let path_tracker = PathTracker {
+        path: Vec::new(),
+        walker: SpecWalker::new(spec),
     };
+    let ss_state = SSState::new(path_tracker, options, 0);
 
     let iter_state = BasicSpecIterState {
         parser: Parser::new(),
@@ -72,35 +86,143 @@ pub fn parse_iter<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Term<'a>> {
         ResourceArc<BasicSpecIterStateWrapper>,
     ) = term.decode()?;
 
-    let (res, mut out_stack) = {
-        let mut resource_inner_guard = resource.0.lock().unwrap();
-        let iter_state = resource_inner_guard.deref_mut();
+    let mut resource_inner_guard = resource.0.lock().unwrap();
+    let iter_state = resource_inner_guard.deref_mut();
 
+    let binaries = whole(binary);
+    let (res, mut out_stack, sink_error) = {
         let mut ss = StreamingSS {
             env: env,
-            input: SingleBinaryProvider::new(binary),
+            input: StreamingInputProvider {
+                binaries: &binaries,
+                end: Some(binary.len()),
+            },
             next_reschedule: iter_state.ss_state.position + 40_000,
             out_stack: stack,
             state: &mut iter_state.ss_state,
+            yields: None,
+            error: None,
         };
 
         let res = iter_state.parser.run(&mut ss);
-        (res, ss.out_stack)
+        (res, ss.out_stack, ss.error)
     };
 
     match res {
         Ok(()) => {
+            let ss_state = &mut iter_state.ss_state;
+            if !ss_state.violations.is_empty() {
+                let violations = ::std::mem::replace(&mut ss_state.violations, Vec::new());
+                let error = DecodeError::new(
+                    ErrorReason::ConstraintViolations(violations),
+                    ss_state.position,
+                    String::new(),
+                );
+                let error = format_error(env, binary, error);
+                return Ok((::atoms::error(), error).encode(env));
+            }
+
             let result = out_stack.pop().unwrap();
             Ok((::atoms::ok(), result).encode(env))
         }
-        Err(ParseError::SourceBail(())) => {
-            let state = (binary, out_stack, resource).encode(env);
+        Err(ParseError::SourceBail(BailType::Reschedule)) => {
+            let state = (binary, out_stack, &resource).encode(env);
             Ok((::atoms::iter(), state).encode(env))
         }
+        Err(ParseError::SourceBail(_)) => {
+            let error = sink_error.unwrap_or_else(|| {
+                let ss_state = &iter_state.ss_state;
+                DecodeError::internal(
+                    "sink failed",
+                    ss_state.position,
+                    ss_state.path_tracker.json_pointer(),
+                )
+            });
+            let error = format_error(env, binary, error);
+            Ok((::atoms::error(), error).encode(env))
+        }
         Err(ParseError::Unexpected(pos, reason)) => {
-            let error = format_unexpected(env, pos, reason);
+            let path_tracker = &iter_state.ss_state.path_tracker;
+            let error = format_unexpected(env, binary, path_tracker, pos, reason);
+            Ok((::atoms::error(), error).encode(env))
+        }
+        // A failed sink is reported the same whichever way the parser
+        // passes its bail on.
+        Err(err) => {
+            let error = sink_error.unwrap_or_else(|| {
+                let ss_state = &iter_state.ss_state;
+                DecodeError::internal(
+                    &format!("{:?}", err),
+                    ss_state.position,
+                    ss_state.path_tracker.json_pointer(),
+                )
+            });
+            let error = format_error(env, binary, error);
             Ok((::atoms::error(), error).encode(env))
         }
-        Err(_) => panic!("TODO: Add proper error"),
     }
 }
+
+/// Decodes a single value again, for `tagged_map` nodes once their tag
+/// is known. `binaries` hold just the value, at the positions it has in
+/// the document, and `line` is the line it starts on along with where
+/// that line starts. `path_tracker` is at the value, matching it against
+/// the node picked by the tag. Constraint violations are returned along
+/// with the term, for the caller to report with its own. Errors carry
+/// their line, the caller has counted lines past them.
+///
+/// Runs to completion without rescheduling, the caller counts the work
+/// against its time slice. Streams inside the value are not yielded.
+pub fn parse_node<'a>(
+    env: Env<'a>,
+    binaries: &[(Range<usize>, Binary<'a>)],
+    line: (usize, usize),
+    path_tracker: PathTracker,
+    options: &ParseOptions,
+    depth: usize,
+) -> Result<(Term<'a>, Vec<(ErrorReason, String)>), DecodeError> {
+    let mut ss_state = SSState::new(path_tracker, options.clone(), depth);
+    ss_state.options.documents = Documents::Single;
+    ss_state.position = binaries.first().map_or(0, |&(ref range, _)| range.start);
+    ss_state.line = line.0;
+    ss_state.line_start = line.1;
+    let mut parser = Parser::new();
+
+    let end = binaries.last().map_or(0, |&(ref range, _)| range.end);
+    let (res, mut out_stack, sink_error) = {
+        let mut ss = StreamingSS {
+            env: env,
+            input: StreamingInputProvider {
+                binaries: binaries,
+                end: Some(end),
+            },
+            next_reschedule: usize::max_value(),
+            out_stack: Vec::new(),
+            state: &mut ss_state,
+            yields: None,
+            error: None,
+        };
+
+        let res = parser.run(&mut ss);
+        (res, ss.out_stack, ss.error)
+    };
+
+    let path = ss_state.path_tracker.json_pointer();
+    let line = (ss_state.line, ss_state.line_start);
+    let error = match res {
+        Ok(()) => return Ok((out_stack.pop().unwrap(), ss_state.violations)),
+        Err(ParseError::SourceBail(_)) => sink_error.unwrap_or_else(|| {
+            DecodeError::internal("parse stopped early", ss_state.position, path)
+        }),
+        Err(ParseError::Unexpected(pos, reason)) => {
+            DecodeError::new(ErrorReason::Unexpected(reason), pos.0, path)
+        }
+        Err(err) => sink_error.unwrap_or_else(|| {
+            DecodeError::internal(&format!("{:?}", err), ss_state.position, path)
+        }),
+    };
+    Err(DecodeError {
+        line: Some(line),
+        ..error
+    })
+}
@@ -0,0 +1,502 @@
+use rustler::resource::ResourceArc;
+use rustler::types::atom::Atom;
+use rustler::types::binary::Binary;
+use rustler::types::list::ListIterator;
+use rustler::types::map::MapIterator;
+use rustler::{Encoder, Env, Error, NifResult, Term};
+
+use std::ops::DerefMut;
+use std::sync::Mutex;
+
+mod writer;
+use numbers::bigint_term_to_string;
+use strings::binary_from_slice;
+
+use self::writer::{write_canonical_float, write_float, write_integer, write_string};
+
+use std::cmp::Ordering;
+
+mod atoms {
+    atoms! {
+        array,
+        object,
+        value,
+        unsupported_type,
+        invalid_string,
+        improper_list,
+        duplicate_key,
+        integer_out_of_range,
+        out_of_memory,
+        pretty,
+        canonical,
+        indent,
+        line_separator,
+        after_colon,
+    }
+}
+
+/// Work done before the encoder yields back to the scheduler, counted
+/// in output bytes written and in map pairs collected.
+const RESCHEDULE_BYTES: usize = 40_000;
+
+#[derive(Copy, Clone)]
+enum FrameKind {
+    Array,
+    Object,
+    /// A value that is not started yet.
+    Value,
+}
+
+/// A container that is partially written. `rest` is the list of
+/// remaining elements, for objects this is a list of `{key, value}`
+/// tuples. For a `Value` frame it is the value itself.
+struct Frame<'a> {
+    kind: FrameKind,
+    rest: Term<'a>,
+    first: bool,
+}
+
+impl<'a> Encoder for Frame<'a> {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let kind = match self.kind {
+            FrameKind::Array => atoms::array(),
+            FrameKind::Object => atoms::object(),
+            FrameKind::Value => atoms::value(),
+        };
+        (kind, self.rest, self.first).encode(env)
+    }
+}
+
+impl<'a> Frame<'a> {
+    fn decode(term: Term<'a>) -> NifResult<Frame<'a>> {
+        let (kind, rest, first): (Atom, Term<'a>, bool) = term.decode()?;
+        let kind = if kind == atoms::array() {
+            FrameKind::Array
+        } else if kind == atoms::object() {
+            FrameKind::Object
+        } else if kind == atoms::value() {
+            FrameKind::Value
+        } else {
+            return Err(Error::BadArg);
+        };
+        Ok(Frame {
+            kind: kind,
+            rest: rest,
+            first: first,
+        })
+    }
+}
+
+enum EncodeError<'a> {
+    UnsupportedType(Term<'a>),
+    InvalidString(Term<'a>),
+    ImproperList(Term<'a>),
+    DuplicateKey(Vec<u8>),
+    /// An integer a double does not hold exactly, in canonical mode.
+    IntegerOutOfRange(Term<'a>),
+    OutOfMemory,
+}
+
+impl<'a> Encoder for EncodeError<'a> {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let reason = match self {
+            &EncodeError::UnsupportedType(term) => (atoms::unsupported_type(), term).encode(env),
+            &EncodeError::InvalidString(term) => (atoms::invalid_string(), term).encode(env),
+            &EncodeError::ImproperList(term) => (atoms::improper_list(), term).encode(env),
+            &EncodeError::DuplicateKey(ref key) => {
+                let key = String::from_utf8_lossy(key);
+                (atoms::duplicate_key(), key.as_ref()).encode(env)
+            }
+            &EncodeError::IntegerOutOfRange(term) => {
+                (atoms::integer_out_of_range(), term).encode(env)
+            }
+            &EncodeError::OutOfMemory => atoms::out_of_memory().encode(env),
+        };
+        (::atoms::error(), reason).encode(env)
+    }
+}
+
+/// Why encoding stopped before the end.
+enum Stop<'a> {
+    Failed(EncodeError<'a>),
+    /// The continuation state handed back to `encode_iter` was not one
+    /// the encoder produced.
+    BadArg,
+}
+
+impl<'a> From<EncodeError<'a>> for Stop<'a> {
+    fn from(error: EncodeError<'a>) -> Stop<'a> {
+        Stop::Failed(error)
+    }
+}
+
+/// Whitespace inserted by the pretty printer.
+pub struct Pretty {
+    indent: Vec<u8>,
+    line_separator: Vec<u8>,
+    after_colon: Vec<u8>,
+}
+
+pub struct EncodeOptions {
+    /// When set, output is pretty printed and object keys are sorted so
+    /// that the same input always produces the same bytes.
+    pretty: Option<Pretty>,
+    /// Output follows RFC 8785 (JSON Canonicalization Scheme).
+    canonical: bool,
+}
+
+fn read_opts(term: Term) -> NifResult<EncodeOptions> {
+    let iterator: ListIterator = term.decode()?;
+
+    let mut pretty_enabled = false;
+    let mut canonical = false;
+    let mut pretty = Pretty {
+        indent: b"  ".to_vec(),
+        line_separator: b"\n".to_vec(),
+        after_colon: b" ".to_vec(),
+    };
+
+    for decoded in iterator.map(|term| term.decode::<(Term, Term)>()) {
+        let (key, value) = decoded?;
+
+        if atoms::pretty() == key {
+            pretty_enabled = value.decode()?;
+        } else if atoms::canonical() == key {
+            canonical = value.decode()?;
+        } else if atoms::indent() == key {
+            pretty.indent = match value.decode::<usize>() {
+                Ok(spaces) => vec![b' '; spaces],
+                Err(_) => value.decode::<Binary>()?.as_slice().to_vec(),
+            };
+        } else if atoms::line_separator() == key {
+            pretty.line_separator = value.decode::<Binary>()?.as_slice().to_vec();
+        } else if atoms::after_colon() == key {
+            pretty.after_colon = value.decode::<Binary>()?.as_slice().to_vec();
+        }
+    }
+
+    // Canonical output does not allow insignificant whitespace.
+    if canonical && pretty_enabled {
+        return Err(Error::BadArg);
+    }
+
+    Ok(EncodeOptions {
+        pretty: if pretty_enabled { Some(pretty) } else { None },
+        canonical: canonical,
+    })
+}
+
+pub struct EncodeState {
+    buf: Vec<u8>,
+    options: EncodeOptions,
+}
+pub struct EncodeStateWrapper(Mutex<EncodeState>);
+
+struct Encoding<'a, 'b> {
+    env: Env<'a>,
+    stack: Vec<Frame<'a>>,
+    buf: &'b mut Vec<u8>,
+    options: &'b EncodeOptions,
+    /// Length of `buf` when this slice started.
+    start: usize,
+    /// Map pairs collected in this slice.
+    collected: usize,
+}
+
+impl<'a, 'b> Encoding<'a, 'b> {
+    /// Work done in this slice, to be compared with `RESCHEDULE_BYTES`.
+    fn spent(&self) -> usize {
+        self.buf.len() - self.start + self.collected
+    }
+
+    fn value(&mut self, term: Term<'a>) -> Result<(), EncodeError<'a>> {
+        if term.is_map() {
+            if term.map_get(::atoms::__struct__().encode(self.env)).is_ok() {
+                return Err(EncodeError::UnsupportedType(term));
+            }
+            // The pairs of a map are collected all at once. A map that
+            // does not fit in what is left of this slice starts the next.
+            let size = term.map_size().unwrap_or(0);
+            if self.spent() > 0 && self.spent() + size > RESCHEDULE_BYTES {
+                self.stack.push(Frame {
+                    kind: FrameKind::Value,
+                    rest: term,
+                    first: true,
+                });
+                // Ends the slice.
+                self.collected = RESCHEDULE_BYTES;
+                return Ok(());
+            }
+            self.collected += size;
+
+            let mut pairs: Vec<(Term<'a>, Term<'a>)> = Vec::with_capacity(size);
+            for (key, value) in MapIterator::new(term).unwrap() {
+                pairs.push((key, value));
+            }
+            if self.options.pretty.is_some() || self.options.canonical {
+                pairs = self.sorted_pairs(pairs)?;
+            }
+            self.buf.push(b'{');
+            self.stack.push(Frame {
+                kind: FrameKind::Object,
+                rest: pairs.encode(self.env),
+                first: true,
+            });
+        } else if term.is_list() || term.is_empty_list() {
+            self.buf.push(b'[');
+            self.stack.push(Frame {
+                kind: FrameKind::Array,
+                rest: term,
+                first: true,
+            });
+        } else if term.is_binary() {
+            self.string(term)?;
+        } else if term.is_atom() {
+            if let Ok(val) = term.decode::<bool>() {
+                let literal: &[u8] = if val { b"true" } else { b"false" };
+                self.buf.extend_from_slice(literal);
+            } else if ::atoms::nil() == term {
+                self.buf.extend_from_slice(b"null");
+            } else {
+                self.string(term)?;
+            }
+        } else if let Ok(number) = term.decode::<f64>() {
+            if self.options.canonical {
+                write_canonical_float(self.buf, number);
+            } else {
+                write_float(self.buf, number);
+            }
+        } else {
+            if self.options.canonical && !is_safe_integer(term) {
+                return Err(EncodeError::IntegerOutOfRange(term));
+            }
+            if !write_integer(self.buf, term) {
+                return Err(EncodeError::UnsupportedType(term));
+            }
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, term: Term<'a>) -> Result<(), EncodeError<'a>> {
+        let string = string_bytes(term)?;
+        write_string(self.buf, &string);
+        Ok(())
+    }
+
+    /// Converts the keys of an object to binaries and sorts the pairs
+    /// by them. In canonical mode keys are compared by their UTF-16 code
+    /// units, and keys that end up identical are an error.
+    fn sorted_pairs(
+        &self,
+        pairs: Vec<(Term<'a>, Term<'a>)>,
+    ) -> Result<Vec<(Term<'a>, Term<'a>)>, EncodeError<'a>> {
+        let mut keyed: Vec<(Vec<u8>, Term<'a>)> = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs {
+            keyed.push((string_bytes(key)?, value));
+        }
+
+        if self.options.canonical {
+            keyed.sort_by(|a, b| compare_utf16(&a.0, &b.0));
+            for window in keyed.windows(2) {
+                if window[0].0 == window[1].0 {
+                    return Err(EncodeError::DuplicateKey(window[0].0.clone()));
+                }
+            }
+        } else {
+            keyed.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        let mut sorted = Vec::with_capacity(keyed.len());
+        for (key, value) in keyed {
+            match binary_from_slice(self.env, &key) {
+                Some(key) => sorted.push((key, value)),
+                None => return Err(EncodeError::OutOfMemory),
+            }
+        }
+        Ok(sorted)
+    }
+
+    fn newline(&mut self, depth: usize) {
+        let options = self.options;
+        if let Some(ref pretty) = options.pretty {
+            self.buf.extend_from_slice(&pretty.line_separator);
+            for _ in 0..depth {
+                self.buf.extend_from_slice(&pretty.indent);
+            }
+        }
+    }
+
+    /// Runs until the stack is empty or until `RESCHEDULE_BYTES` of work
+    /// have been done. Returns true when done.
+    fn run(&mut self) -> Result<bool, Stop<'a>> {
+        while let Some(frame) = self.stack.pop() {
+            if self.spent() >= RESCHEDULE_BYTES {
+                self.stack.push(frame);
+                return Ok(false);
+            }
+
+            if let FrameKind::Value = frame.kind {
+                self.value(frame.rest)?;
+                continue;
+            }
+
+            if frame.rest.is_empty_list() {
+                if !frame.first {
+                    let depth = self.stack.len();
+                    self.newline(depth);
+                }
+                self.buf.push(match frame.kind {
+                    FrameKind::Object => b'}',
+                    _ => b']',
+                });
+                continue;
+            }
+
+            let (head, tail) = match frame.rest.list_get_cell() {
+                Ok(cell) => cell,
+                Err(_) => return Err(EncodeError::ImproperList(frame.rest).into()),
+            };
+            self.stack.push(Frame {
+                kind: frame.kind,
+                rest: tail,
+                first: false,
+            });
+
+            if !frame.first {
+                self.buf.push(b',');
+            }
+            let depth = self.stack.len();
+            self.newline(depth);
+
+            match frame.kind {
+                FrameKind::Object => {
+                    let (key, value): (Term<'a>, Term<'a>) =
+                        head.decode().map_err(|_| Stop::BadArg)?;
+                    self.string(key)?;
+                    self.buf.push(b':');
+                    if let Some(ref pretty) = self.options.pretty {
+                        self.buf.extend_from_slice(&pretty.after_colon);
+                    }
+                    self.value(value)?;
+                }
+                _ => self.value(head)?,
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Largest integer magnitude RFC 8785 output can hold, it serializes
+/// numbers as doubles.
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// Whether `term` is an integer a double holds exactly. Anything that
+/// is not an integer is left for `write_integer` to reject.
+fn is_safe_integer(term: Term) -> bool {
+    match term.decode::<i64>() {
+        Ok(number) => -MAX_SAFE_INTEGER <= number && number <= MAX_SAFE_INTEGER,
+        // Wider integers do not fit either.
+        Err(_) => term.decode::<u64>().is_err() && bigint_term_to_string(term).is_none(),
+    }
+}
+
+/// Compares two UTF-8 strings by their UTF-16 code units.
+fn compare_utf16(a: &[u8], b: &[u8]) -> Ordering {
+    // Both have been validated as UTF-8 by `string_bytes`.
+    let a = unsafe { ::std::str::from_utf8_unchecked(a) };
+    let b = unsafe { ::std::str::from_utf8_unchecked(b) };
+    a.encode_utf16().cmp(b.encode_utf16())
+}
+
+/// Returns the bytes of a string-like term. Atoms and integers are
+/// accepted so that they can be used as object keys.
+fn string_bytes<'a>(term: Term<'a>) -> Result<Vec<u8>, EncodeError<'a>> {
+    if term.is_atom() {
+        let string = term
+            .atom_to_string()
+            .map_err(|_| EncodeError::UnsupportedType(term))?;
+        Ok(string.into_bytes())
+    } else if term.is_binary() {
+        let binary: Binary = term
+            .decode()
+            .map_err(|_| EncodeError::UnsupportedType(term))?;
+        if ::std::str::from_utf8(binary.as_slice()).is_err() {
+            return Err(EncodeError::InvalidString(term));
+        }
+        Ok(binary.as_slice().to_vec())
+    } else {
+        let mut number: Vec<u8> = Vec::new();
+        if !write_integer(&mut number, term) {
+            return Err(EncodeError::UnsupportedType(term));
+        }
+        Ok(number)
+    }
+}
+
+fn encode_inner<'a>(
+    env: Env<'a>,
+    stack: Vec<Frame<'a>>,
+    state: &mut EncodeState,
+) -> NifResult<Result<Term<'a>, Vec<Frame<'a>>>> {
+    let start = state.buf.len();
+    let mut encoding = Encoding {
+        env: env,
+        stack: stack,
+        buf: &mut state.buf,
+        options: &state.options,
+        start: start,
+        collected: 0,
+    };
+
+    match encoding.run() {
+        Ok(true) => match binary_from_slice(env, &state.buf) {
+            Some(binary) => Ok(Ok((::atoms::ok(), binary).encode(env))),
+            None => Ok(Ok(EncodeError::OutOfMemory.encode(env))),
+        },
+        Ok(false) => Ok(Err(encoding.stack)),
+        Err(Stop::Failed(error)) => Ok(Ok(error.encode(env))),
+        Err(Stop::BadArg) => Err(Error::BadArg),
+    }
+}
+
+pub fn encode_init<'a>(env: Env<'a>, term: Term<'a>, opts: Term<'a>) -> NifResult<Term<'a>> {
+    let mut state = EncodeState {
+        buf: Vec::new(),
+        options: read_opts(opts)?,
+    };
+
+    let stack = vec![Frame {
+        kind: FrameKind::Value,
+        rest: term,
+        first: true,
+    }];
+
+    match encode_inner(env, stack, &mut state)? {
+        Ok(res) => Ok(res),
+        Err(stack) => {
+            let resource = ResourceArc::new(EncodeStateWrapper(Mutex::new(state)));
+            Ok((::atoms::iter(), stack, resource).encode(env))
+        }
+    }
+}
+
+pub fn encode_iter<'a>(
+    env: Env<'a>,
+    stack_term: Term<'a>,
+    resource_term: Term<'a>,
+) -> NifResult<Term<'a>> {
+    let stack_terms: Vec<Term<'a>> = stack_term.decode()?;
+    let mut stack: Vec<Frame<'a>> = Vec::with_capacity(stack_terms.len());
+    for term in stack_terms {
+        stack.push(Frame::decode(term)?);
+    }
+
+    let resource: ResourceArc<EncodeStateWrapper> = resource_term.decode()?;
+    let mut resource_inner_guard = resource.0.lock().unwrap();
+    let resource_inner = resource_inner_guard.deref_mut();
+
+    match encode_inner(env, stack, resource_inner)? {
+        Ok(res) => Ok(res),
+        Err(stack) => Ok((::atoms::iter(), stack, resource_term).encode(env)),
+    }
+}
@@ -0,0 +1,65 @@
+use rustler::Term;
+
+use numbers::{bigint_term_to_string, float_to_es6_string};
+
+const HEX_DIGITS: &'static [u8; 16] = b"0123456789abcdef";
+
+/// Writes a JSON string literal. The input is expected to already be
+/// valid UTF-8, only the characters JSON requires are escaped.
+pub fn write_string(buf: &mut Vec<u8>, string: &[u8]) {
+    buf.push(b'"');
+
+    let mut start = 0;
+    for (idx, &byte) in string.iter().enumerate() {
+        let escape: &[u8] = match byte {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x08 => b"\\b",
+            0x0C => b"\\f",
+            0x00..=0x1F => b"",
+            _ => continue,
+        };
+
+        buf.extend_from_slice(&string[start..idx]);
+        if escape.is_empty() {
+            buf.extend_from_slice(b"\\u00");
+            buf.push(HEX_DIGITS[(byte >> 4) as usize]);
+            buf.push(HEX_DIGITS[(byte & 0xF) as usize]);
+        } else {
+            buf.extend_from_slice(escape);
+        }
+        start = idx + 1;
+    }
+    buf.extend_from_slice(&string[start..]);
+
+    buf.push(b'"');
+}
+
+/// Writes a float in its shortest representation that reads back into
+/// the same value.
+pub fn write_float(buf: &mut Vec<u8>, number: f64) {
+    buf.extend_from_slice(format!("{:?}", number).as_bytes());
+}
+
+/// Writes a float as specified by RFC 8785.
+pub fn write_canonical_float(buf: &mut Vec<u8>, number: f64) {
+    buf.extend_from_slice(float_to_es6_string(number).as_bytes());
+}
+
+/// Writes an integer term, small or big. Returns false if the term is
+/// not an integer.
+pub fn write_integer(buf: &mut Vec<u8>, term: Term) -> bool {
+    if let Ok(number) = term.decode::<i64>() {
+        buf.extend_from_slice(number.to_string().as_bytes());
+    } else if let Ok(number) = term.decode::<u64>() {
+        buf.extend_from_slice(number.to_string().as_bytes());
+    } else if let Some(number) = bigint_term_to_string(term) {
+        buf.extend_from_slice(number.as_bytes());
+    } else {
+        return false;
+    }
+    true
+}
@@ -0,0 +1,395 @@
+use iterative_json_parser::Unexpected;
+
+use rustler::types::atom::Atom;
+use rustler::types::map::map_new;
+use rustler::{Encoder, Env, Term};
+
+use options::Limit;
+use tree_spec::{SpecError, ValueType};
+
+use std::cmp::min;
+
+mod atoms {
+    atoms! {
+        reason,
+        message,
+        offset,
+        line,
+        column,
+        path,
+        snippet,
+        juicy_error = "Elixir.Juicy.Error",
+        unsupported_spec,
+        internal_error,
+        duplicate_key,
+        missing_atom,
+        max_depth_exceeded,
+        max_string_bytes_exceeded,
+        max_number_digits_exceeded,
+        max_keys_per_object_exceeded,
+        max_total_bytes_exceeded,
+        wrong_arity,
+        invalid_enum_value,
+        missing_tag,
+        unknown_tag,
+        missing_keys,
+        missing_key,
+        unknown_key,
+        details,
+        constraint_violations,
+        too_short,
+        too_long,
+        pattern_mismatch,
+        invalid_format,
+        too_small,
+        too_large,
+        not_integer,
+        type_mismatch,
+        expected_newline,
+        expected_separator,
+        truncated,
+    }
+}
+
+/// Number of bytes included on either side of the error position in
+/// the snippet.
+const SNIPPET_CONTEXT: usize = 16;
+
+#[derive(Debug)]
+pub enum ErrorReason {
+    Unexpected(Unexpected),
+    Spec(SpecError),
+    DuplicateKey(Vec<u8>),
+    /// A key has no existing atom and `keys: {:existing_atoms, :error}`
+    /// was given.
+    MissingAtom(Vec<u8>),
+    LimitExceeded(Limit),
+    /// A `tuple` node got an array of the wrong length.
+    WrongArity {
+        expected: usize,
+        found: usize,
+    },
+    /// An `enum` node without a default got a string outside its set,
+    /// or `None` for a value that is not a string.
+    InvalidEnumValue(Option<Vec<u8>>),
+    /// A `tagged_map` object has no string under its key name.
+    MissingTag(Vec<u8>),
+    /// A `tagged_map` object has a tag with no mapping and no default.
+    UnknownTag(Vec<u8>),
+    /// Required keys of an object are missing.
+    MissingKeys(Vec<Vec<u8>>),
+    /// One of the keys of `MissingKeys`, in the error details.
+    MissingKey(Vec<u8>),
+    /// A key without an atom on a map node with `reject_non_atoms`.
+    UnknownKey(Vec<u8>),
+    /// Values failed constraints of their spec nodes, each with the path
+    /// of the value.
+    ConstraintViolations(Vec<(ErrorReason, String)>),
+    /// The constraints, in the details of `ConstraintViolations`.
+    TooShort(usize),
+    TooLong(usize),
+    PatternMismatch(String),
+    InvalidFormat(&'static str),
+    TooSmall(f64),
+    TooLarge(f64),
+    NotInteger,
+    /// A value of a type other than these.
+    TypeMismatch(Vec<ValueType>),
+    /// A document of a stream with one per line is followed by more
+    /// than whitespace on its line.
+    ExpectedNewline,
+    /// Input of a JSON text sequence does not start with a record
+    /// separator.
+    ExpectedSeparator,
+    /// A document of a stream of several was cut off, by the end of the
+    /// input or of its line or record.
+    Truncated,
+    /// Something went wrong that is not the fault of the input, like a
+    /// failed allocation.
+    Internal(String),
+}
+
+impl ErrorReason {
+    /// A machine readable atom for the reason. For syntax errors this is
+    /// the snake cased variant name of the parser's `Unexpected`.
+    fn atom<'a>(&self, env: Env<'a>) -> Atom {
+        match self {
+            &ErrorReason::Unexpected(ref unexpected) => {
+                let name = snake_case(&format!("{:?}", unexpected));
+                Atom::from_str(env, &name).unwrap_or(atoms::internal_error())
+            }
+            &ErrorReason::Spec(SpecError::Unsupported(_)) => atoms::unsupported_spec(),
+            &ErrorReason::DuplicateKey(_) => atoms::duplicate_key(),
+            &ErrorReason::MissingAtom(_) => atoms::missing_atom(),
+            &ErrorReason::LimitExceeded(Limit::Depth) => atoms::max_depth_exceeded(),
+            &ErrorReason::LimitExceeded(Limit::StringBytes) => atoms::max_string_bytes_exceeded(),
+            &ErrorReason::LimitExceeded(Limit::NumberDigits) => atoms::max_number_digits_exceeded(),
+            &ErrorReason::LimitExceeded(Limit::KeysPerObject) => {
+                atoms::max_keys_per_object_exceeded()
+            }
+            &ErrorReason::LimitExceeded(Limit::TotalBytes) => atoms::max_total_bytes_exceeded(),
+            &ErrorReason::WrongArity { .. } => atoms::wrong_arity(),
+            &ErrorReason::InvalidEnumValue(_) => atoms::invalid_enum_value(),
+            &ErrorReason::MissingTag(_) => atoms::missing_tag(),
+            &ErrorReason::UnknownTag(_) => atoms::unknown_tag(),
+            &ErrorReason::MissingKeys(_) => atoms::missing_keys(),
+            &ErrorReason::MissingKey(_) => atoms::missing_key(),
+            &ErrorReason::UnknownKey(_) => atoms::unknown_key(),
+            &ErrorReason::ConstraintViolations(_) => atoms::constraint_violations(),
+            &ErrorReason::TooShort(_) => atoms::too_short(),
+            &ErrorReason::TooLong(_) => atoms::too_long(),
+            &ErrorReason::PatternMismatch(_) => atoms::pattern_mismatch(),
+            &ErrorReason::InvalidFormat(_) => atoms::invalid_format(),
+            &ErrorReason::TooSmall(_) => atoms::too_small(),
+            &ErrorReason::TooLarge(_) => atoms::too_large(),
+            &ErrorReason::NotInteger => atoms::not_integer(),
+            &ErrorReason::TypeMismatch(_) => atoms::type_mismatch(),
+            &ErrorReason::ExpectedNewline => atoms::expected_newline(),
+            &ErrorReason::ExpectedSeparator => atoms::expected_separator(),
+            &ErrorReason::Truncated => atoms::truncated(),
+            &ErrorReason::Internal(_) => atoms::internal_error(),
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            &ErrorReason::Unexpected(ref unexpected) => unexpected.explain().to_string(),
+            &ErrorReason::Spec(SpecError::Unsupported(feature)) => {
+                format!("{} is not supported here", feature)
+            }
+            &ErrorReason::DuplicateKey(ref key) => {
+                format!("duplicate key \"{}\"", String::from_utf8_lossy(key))
+            }
+            &ErrorReason::MissingAtom(ref key) => {
+                format!(
+                    "no existing atom for key \"{}\"",
+                    String::from_utf8_lossy(key)
+                )
+            }
+            &ErrorReason::LimitExceeded(limit) => {
+                let what = match limit {
+                    Limit::Depth => "nesting depth",
+                    Limit::StringBytes => "string length",
+                    Limit::NumberDigits => "number of digits in a number",
+                    Limit::KeysPerObject => "number of keys in an object",
+                    Limit::TotalBytes => "input size",
+                };
+                format!("{} exceeds the configured maximum", what)
+            }
+            &ErrorReason::WrongArity { expected, found } => {
+                format!("expected {} elements, found {}", expected, found)
+            }
+            &ErrorReason::InvalidEnumValue(Some(ref value)) => {
+                format!(
+                    "\"{}\" is not one of the allowed values",
+                    String::from_utf8_lossy(value)
+                )
+            }
+            &ErrorReason::InvalidEnumValue(None) => {
+                "expected one of the allowed strings".to_string()
+            }
+            &ErrorReason::MissingTag(ref key) => {
+                format!(
+                    "object has no string \"{}\" key",
+                    String::from_utf8_lossy(key)
+                )
+            }
+            &ErrorReason::UnknownTag(ref tag) => {
+                format!("unknown tag \"{}\"", String::from_utf8_lossy(tag))
+            }
+            &ErrorReason::MissingKeys(ref keys) => {
+                let keys: Vec<String> = keys
+                    .iter()
+                    .map(|key| format!("\"{}\"", String::from_utf8_lossy(key)))
+                    .collect();
+                format!("missing required keys {}", keys.join(", "))
+            }
+            &ErrorReason::MissingKey(ref key) => {
+                format!("missing required key \"{}\"", String::from_utf8_lossy(key))
+            }
+            &ErrorReason::UnknownKey(ref key) => {
+                format!("unknown key \"{}\"", String::from_utf8_lossy(key))
+            }
+            &ErrorReason::ConstraintViolations(ref violations) => match violations.len() {
+                1 => "1 value does not meet its constraints".to_string(),
+                count => format!("{} values do not meet their constraints", count),
+            },
+            &ErrorReason::TooShort(min) => format!("shorter than {} characters", min),
+            &ErrorReason::TooLong(max) => format!("longer than {} characters", max),
+            &ErrorReason::PatternMismatch(ref pattern) => {
+                format!("does not match the pattern {}", pattern)
+            }
+            &ErrorReason::InvalidFormat(format) => format!("not a valid {}", format),
+            &ErrorReason::TooSmall(min) => format!("less than the minimum of {}", min),
+            &ErrorReason::TooLarge(max) => format!("greater than the maximum of {}", max),
+            &ErrorReason::NotInteger => "not an integer".to_string(),
+            &ErrorReason::TypeMismatch(ref types) if types.is_empty() => {
+                "no value is allowed here".to_string()
+            }
+            &ErrorReason::TypeMismatch(ref types) => {
+                let names: Vec<&str> = types.iter().map(|&typ| typ.name()).collect();
+                format!("expected {}", names.join(" or "))
+            }
+            &ErrorReason::ExpectedNewline => "expected a new line after the document".to_string(),
+            &ErrorReason::ExpectedSeparator => "expected a record separator".to_string(),
+            &ErrorReason::Truncated => "the document is cut off".to_string(),
+            &ErrorReason::Internal(ref message) => message.clone(),
+        }
+    }
+
+    /// The individual problems of a reason that groups several, each
+    /// with its own path. `path` is that of the error itself.
+    fn details<'a>(&self, env: Env<'a>, path: &str) -> Vec<Term<'a>> {
+        match self {
+            &ErrorReason::MissingKeys(ref keys) => keys
+                .iter()
+                .map(|key| {
+                    let mut key_path = path.to_string();
+                    push_pointer_key(&mut key_path, key);
+                    detail_to_term(env, &ErrorReason::MissingKey(key.clone()), &key_path)
+                })
+                .collect(),
+            &ErrorReason::ConstraintViolations(ref violations) => violations
+                .iter()
+                .map(|&(ref reason, ref path)| detail_to_term(env, reason, path))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for character in name.chars().take_while(|c| c.is_alphanumeric()) {
+        if character.is_uppercase() {
+            if !out.is_empty() {
+                out.push('_');
+            }
+            out.extend(character.to_lowercase());
+        } else {
+            out.push(character);
+        }
+    }
+    out
+}
+
+/// Where in the input an error happened, in human terms.
+pub struct Location {
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in bytes
+    pub column: usize,
+    pub snippet: Vec<u8>,
+}
+
+impl Location {
+    /// Locates an offset in input that is fully available.
+    pub fn from_slice(input: &[u8], offset: usize) -> Location {
+        let offset = min(offset, input.len());
+        let before = &input[..offset];
+
+        let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+        let line_start = match before.iter().rposition(|&b| b == b'\n') {
+            Some(newline) => newline + 1,
+            None => 0,
+        };
+
+        let start = offset.saturating_sub(SNIPPET_CONTEXT);
+        let end = min(offset + SNIPPET_CONTEXT, input.len());
+
+        Location {
+            line: line,
+            column: offset - line_start + 1,
+            snippet: input[start..end].to_vec(),
+        }
+    }
+
+    /// The range of input that should be included in the snippet for an
+    /// error at `offset`.
+    pub fn snippet_range(offset: usize) -> ::std::ops::Range<usize> {
+        offset.saturating_sub(SNIPPET_CONTEXT)..(offset + SNIPPET_CONTEXT)
+    }
+}
+
+/// An error in the input document. Converted into a `%Juicy.Error{}`
+/// once the location has been worked out.
+#[derive(Debug)]
+pub struct DecodeError {
+    pub reason: ErrorReason,
+    pub position: usize,
+    /// JSON Pointer of the value enclosing the error.
+    pub path: String,
+    /// Line of `position` and where that line starts, for errors from
+    /// input the sink has counted lines past.
+    pub line: Option<(usize, usize)>,
+}
+
+impl DecodeError {
+    pub fn new(reason: ErrorReason, position: usize, path: String) -> DecodeError {
+        DecodeError {
+            reason: reason,
+            position: position,
+            path: path,
+            line: None,
+        }
+    }
+
+    pub fn internal(message: &str, position: usize, path: String) -> DecodeError {
+        DecodeError::new(ErrorReason::Internal(message.to_string()), position, path)
+    }
+
+    pub fn to_term<'a>(&self, env: Env<'a>, location: &Location) -> Term<'a> {
+        let snippet = String::from_utf8_lossy(&location.snippet);
+        let details = self.reason.details(env, &self.path);
+        let fields: [(Atom, Term<'a>); 9] = [
+            (::atoms::__struct__(), atoms::juicy_error().encode(env)),
+            (atoms::reason(), self.reason.atom(env).encode(env)),
+            (atoms::message(), self.reason.message().as_str().encode(env)),
+            (atoms::offset(), (self.position as u64).encode(env)),
+            (atoms::line(), (location.line as u64).encode(env)),
+            (atoms::column(), (location.column as u64).encode(env)),
+            (atoms::path(), self.path.as_str().encode(env)),
+            (atoms::snippet(), snippet.as_ref().encode(env)),
+            (atoms::details(), details.encode(env)),
+        ];
+
+        // Putting a new atom key into a map we just created can not fail.
+        let mut map = map_new(env);
+        for &(key, value) in fields.iter() {
+            map = map.map_put(key.encode(env), value).unwrap_or(map);
+        }
+        map
+    }
+}
+
+fn detail_to_term<'a>(env: Env<'a>, reason: &ErrorReason, path: &str) -> Term<'a> {
+    let fields: [(Atom, Term<'a>); 3] = [
+        (atoms::reason(), reason.atom(env).encode(env)),
+        (atoms::message(), reason.message().as_str().encode(env)),
+        (atoms::path(), path.encode(env)),
+    ];
+
+    let mut map = map_new(env);
+    for &(key, value) in fields.iter() {
+        map = map.map_put(key.encode(env), value).unwrap_or(map);
+    }
+    map
+}
+
+/// Appends a reference token for an object key to a JSON Pointer.
+pub fn push_pointer_key(pointer: &mut String, key: &[u8]) {
+    pointer.push('/');
+    for character in String::from_utf8_lossy(key).chars() {
+        match character {
+            '~' => pointer.push_str("~0"),
+            '/' => pointer.push_str("~1"),
+            _ => pointer.push(character),
+        }
+    }
+}
+
+/// Appends a reference token for an array index to a JSON Pointer.
+pub fn push_pointer_index(pointer: &mut String, index: usize) {
+    pointer.push('/');
+    pointer.push_str(&index.to_string());
+}
@@ -15,5 +15,6 @@ pub mod streaming;
 pub trait InputProvider<DataResponse> {
     fn byte(&self, pos: usize) -> DataResponse;
     fn push_range(&self, range: PRange, buf: &mut Vec<u8>);
-    fn range_to_term<'a>(&self, env: Env<'a>, range: PRange) -> Term<'a>;
+    /// Returns `None` if the term could not be created.
+    fn range_to_term<'a>(&self, env: Env<'a>, range: PRange) -> Option<Term<'a>>;
 }
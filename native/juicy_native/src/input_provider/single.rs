@@ -26,11 +26,10 @@ impl<'a> InputProvider<Option<u8>> for SingleBinaryProvider<'a> {
         buf.extend_from_slice(&bin[range.start..range.end]);
     }
 
-    fn range_to_term<'b>(&self, env: Env<'b>, range: PRange) -> Term<'b> {
+    fn range_to_term<'b>(&self, env: Env<'b>, range: PRange) -> Option<Term<'b>> {
         self.binary
             .make_subbinary(range.start, range.end - range.start)
             .ok()
-            .unwrap()
-            .encode(env)
+            .map(|bin| bin.encode(env))
     }
 }
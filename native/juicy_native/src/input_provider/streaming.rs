@@ -1,9 +1,10 @@
-use std::io::Write;
 use std::ops::Range;
 
-use rustler::types::binary::{Binary, OwnedBinary};
+use rustler::types::binary::Binary;
 use rustler::{Encoder, Env, Term};
 
+use strings::binary_from_slice;
+
 use super::InputProvider;
 
 use iterative_json_parser::Range as PRange;
@@ -15,11 +16,40 @@ pub enum StreamingInputResult {
 }
 
 /// Provides input from a set of binaries.
+#[derive(Clone, Copy)]
 pub struct StreamingInputProvider<'a, 'b>
 where
     'a: 'b,
 {
     pub binaries: &'b [(Range<usize>, Binary<'a>)],
+    /// Where the input ends, once the stream has ended.
+    pub end: Option<usize>,
+}
+
+impl<'a, 'b> StreamingInputProvider<'a, 'b> {
+    /// The input in `range`, as sub binaries at the same positions.
+    /// Returns `None` if part of it is not held.
+    pub fn slice(&self, range: PRange) -> Option<Vec<(Range<usize>, Binary<'a>)>> {
+        let mut binaries = Vec::new();
+        let mut covered = 0;
+        for &(ref b_range, bin) in self.binaries {
+            let start = ::std::cmp::max(range.start, b_range.start);
+            let end = ::std::cmp::min(range.end, b_range.end);
+            if start >= end {
+                continue;
+            }
+            let sub = bin
+                .make_subbinary(start - b_range.start, end - start)
+                .ok()?;
+            binaries.push((start..end, sub));
+            covered += end - start;
+        }
+        if covered == range.end - range.start {
+            Some(binaries)
+        } else {
+            None
+        }
+    }
 }
 
 impl<'a, 'b> InputProvider<StreamingInputResult> for StreamingInputProvider<'a, 'b> {
@@ -29,11 +59,17 @@ impl<'a, 'b> InputProvider<StreamingInputResult> for StreamingInputProvider<'a,
                 return StreamingInputResult::Ok(bin.as_slice()[pos - range.start]);
             }
         }
-        StreamingInputResult::AwaitInput
+        match self.end {
+            Some(end) if pos >= end => StreamingInputResult::Eof,
+            _ => StreamingInputResult::AwaitInput,
+        }
     }
 
     fn push_range(&self, range: PRange, buf: &mut Vec<u8>) {
         for &(ref b_range, bin) in self.binaries.iter().rev() {
+            if b_range.end <= range.start || b_range.start >= range.end {
+                continue;
+            }
             let s = if range.start < b_range.start {
                 0
             } else {
@@ -49,13 +85,18 @@ impl<'a, 'b> InputProvider<StreamingInputResult> for StreamingInputProvider<'a,
         }
     }
 
-    fn range_to_term<'c>(&self, env: Env<'c>, range: PRange) -> Term<'c> {
-        // TODO
+    fn range_to_term<'c>(&self, env: Env<'c>, range: PRange) -> Option<Term<'c>> {
+        // A range inside one binary does not have to be copied.
+        for &(ref b_range, bin) in self.binaries {
+            if b_range.start <= range.start && range.end <= b_range.end {
+                return bin
+                    .make_subbinary(range.start - b_range.start, range.end - range.start)
+                    .ok()
+                    .map(|bin| bin.encode(env));
+            }
+        }
         let mut buf: Vec<u8> = Vec::new();
         self.push_range(range, &mut buf);
-
-        let mut bin = OwnedBinary::new(buf.len()).unwrap();
-        bin.as_mut_slice().write(&buf).unwrap();
-        bin.release(env).encode(env)
+        binary_from_slice(env, &buf)
     }
 }
@@ -5,19 +5,29 @@ extern crate rustler_codegen;
 
 extern crate num_bigint;
 extern crate num_traits;
+extern crate regex;
 
 use rustler::{Encoder, Env, NifResult, Term};
 
+use options::ParseOptions;
+
 extern crate iterative_json_parser;
 
+mod errors;
 mod input_provider;
 mod numbers;
+mod objects;
+mod options;
 mod path_tracker;
+mod raw_source;
+mod spec_sink;
+mod spec_terms;
 mod strings;
 mod tree_spec;
 
 mod basic;
 mod basic_spec;
+mod encode;
 mod streaming;
 
 mod atoms {
@@ -31,13 +41,15 @@ mod atoms {
         yield_ = "yield",
         await_input,
         finished,
+        line,
+        document,
         __struct__,
     }
 }
 
 #[rustler::nif]
-fn parse_init<'a>(env: Env<'a>, input_term: Term<'a>) -> NifResult<Term<'a>> {
-    basic::parse(env, input_term)
+fn parse_init<'a>(env: Env<'a>, input_term: Term<'a>, opts_term: Term<'a>) -> NifResult<Term<'a>> {
+    basic::parse(env, input_term, opts_term)
 }
 
 #[rustler::nif]
@@ -50,13 +62,28 @@ fn parse_iter<'a>(
     basic::parse_iter(env, input_term, stack_term, resource_term)
 }
 
+#[rustler::nif]
+fn encode_init<'a>(env: Env<'a>, term: Term<'a>, opts: Term<'a>) -> NifResult<Term<'a>> {
+    encode::encode_init(env, term, opts)
+}
+
+#[rustler::nif]
+fn encode_iter<'a>(
+    env: Env<'a>,
+    stack_term: Term<'a>,
+    resource_term: Term<'a>,
+) -> NifResult<Term<'a>> {
+    encode::encode_iter(env, stack_term, resource_term)
+}
+
 #[rustler::nif]
 fn spec_parse_init<'a>(
     env: Env<'a>,
     binary_term: Term<'a>,
     spec_term: Term<'a>,
+    opts_term: Term<'a>,
 ) -> NifResult<Term<'a>> {
-    basic_spec::parse_init(env, binary_term, spec_term)
+    basic_spec::parse_init(env, binary_term, spec_term, opts_term)
 }
 
 #[rustler::nif]
@@ -65,8 +92,12 @@ fn spec_parse_iter<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Term<'a>> {
 }
 
 #[rustler::nif]
-fn stream_parse_init<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Term<'a>> {
-    streaming::parse_init(env, term)
+fn stream_parse_init<'a>(
+    env: Env<'a>,
+    spec_term: Term<'a>,
+    opts_term: Term<'a>,
+) -> NifResult<Term<'a>> {
+    streaming::parse_init(env, spec_term, opts_term)
 }
 
 #[rustler::nif]
@@ -79,17 +110,32 @@ fn stream_parse_iter<'a>(
 }
 
 #[rustler::nif]
-fn validate_spec<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Term<'a>> {
-    match tree_spec::spec_from_term(term) {
-        Ok(_) => Ok(atoms::ok().encode(env)),
+fn stream_parse_end<'a>(env: Env<'a>, binaries: Term<'a>, parser: Term<'a>) -> NifResult<Term<'a>> {
+    streaming::parse_end(env, binaries, parser)
+}
+
+#[rustler::nif]
+fn compile_spec<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Term<'a>> {
+    match tree_spec::compile_spec(term, &ParseOptions::default()) {
+        Ok(compiled) => Ok((atoms::ok(), compiled).encode(env)),
         Err(_) => Ok(atoms::error().encode(env)),
     }
 }
 
+#[rustler::nif]
+fn compile_json_schema<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Term<'a>> {
+    match tree_spec::compile_schema(env, term, &ParseOptions::default()) {
+        Ok(compiled) => Ok((atoms::ok(), compiled).encode(env)),
+        Err(error) => Ok((atoms::error(), error.to_term(env)).encode(env)),
+    }
+}
+
 fn load<'a>(env: Env<'a>, _load_info: Term<'a>) -> bool {
     resource!(basic::IterStateWrapper, env);
     resource!(basic_spec::BasicSpecIterStateWrapper, env);
     resource!(streaming::StreamingIterStateWrapper, env);
+    resource!(encode::EncodeStateWrapper, env);
+    resource!(tree_spec::CompiledSpec, env);
     true
 }
 
@@ -98,11 +144,15 @@ rustler::init!(
     [
         parse_init,
         parse_iter,
+        encode_init,
+        encode_iter,
         spec_parse_init,
         spec_parse_iter,
         stream_parse_init,
         stream_parse_iter,
-        validate_spec
+        stream_parse_end,
+        compile_spec,
+        compile_json_schema
     ],
     load = load
 );
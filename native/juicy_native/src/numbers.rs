@@ -1,3 +1,4 @@
+use rustler::types::map::map_new;
 use rustler::{Encoder, Env, Term};
 
 use num_bigint::BigUint;
@@ -7,6 +8,18 @@ use std::str::FromStr;
 
 use iterative_json_parser::{NumberData, Range};
 
+use options::Floats;
+use strings::binary_from_slice;
+
+mod atoms {
+    atoms! {
+        decimal = "Elixir.Decimal",
+        sign,
+        coef,
+        exp,
+    }
+}
+
 fn integer_to_bigint_term<'a>(env: Env<'a>, sign: bool, number: &str) -> Term<'a> {
     // http://erlang.org/doc/apps/erts/erl_ext_dist.html#id101259
 
@@ -36,6 +49,88 @@ fn integer_to_bigint_term<'a>(env: Env<'a>, sign: bool, number: &str) -> Term<'a
     term
 }
 
+/// Reads a bignum term back into its decimal representation. This goes
+/// through the external term format, the reverse of `integer_to_bigint_term`.
+pub fn bigint_term_to_string(term: Term) -> Option<String> {
+    let bin = term.to_binary();
+    let data = bin.as_slice();
+
+    let (sign, digits) = match data.get(1) {
+        // small bignum tag
+        Some(&110) if data.len() > 4 => (data[3], &data[4..]),
+        // large bignum tag
+        Some(&111) if data.len() > 7 => (data[6], &data[7..]),
+        _ => return None,
+    };
+
+    let num = BigUint::from_bytes_le(digits);
+    let mut string = String::new();
+    if sign != 0 {
+        string.push('-');
+    }
+    string.push_str(&num.to_str_radix(10));
+    Some(string)
+}
+
+/// Formats a float the way ECMAScript's `Number.prototype.toString` does,
+/// as required by RFC 8785. The digits are the shortest ones that read
+/// back into the same value.
+pub fn float_to_es6_string(number: f64) -> String {
+    if number == 0.0 {
+        return "0".to_string();
+    }
+
+    // Rust's `{:e}` formatting yields the shortest round-trip digits,
+    // we only need to lay them out differently.
+    let scientific = format!("{:e}", number.abs());
+    let mut parts = scientific.splitn(2, 'e');
+    let mantissa = parts.next().unwrap();
+    let exponent: i32 = parts.next().unwrap().parse().unwrap();
+
+    let digits: String = mantissa.chars().filter(|&c| c != '.').collect();
+    let k = digits.len() as i32;
+    let n = exponent + 1;
+
+    let mut out = String::new();
+    if number < 0.0 {
+        out.push('-');
+    }
+
+    if k <= n && n <= 21 {
+        out.push_str(&digits);
+        for _ in 0..(n - k) {
+            out.push('0');
+        }
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        for _ in 0..(-n) {
+            out.push('0');
+        }
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push('e');
+        out.push(if n - 1 < 0 { '-' } else { '+' });
+        out.push_str(&(n - 1).abs().to_string());
+    }
+    out
+}
+
+/// Number of digits in a number, counting the integer, decimal and
+/// exponent parts.
+pub fn number_digits(data: &NumberData) -> usize {
+    let len = |range: Range| range.end - range.start;
+    len(data.integer) + data.decimal.map_or(0, &len) + data.exponent.map_or(0, &len)
+}
+
 fn integer_to_term<'a>(env: Env<'a>, sign: bool, num_str: &str) -> Term<'a> {
     if sign {
         match u64::from_str(num_str) {
@@ -55,7 +150,100 @@ fn float_to_term<'a>(env: Env<'a>, num_str: &str) -> Term<'a> {
     number.encode(env)
 }
 
-pub fn number_data_to_term<F>(env: Env, data: NumberData, range_provider: F) -> Term
+/// Builds a `%Decimal{}` straight from the digits, so no precision is
+/// lost. Bignums are used where needed.
+fn decimal_to_term<'a, F>(env: Env<'a>, data: &NumberData, range_provider: F) -> Term<'a>
+where
+    F: Fn(Range, &mut Vec<u8>),
+{
+    let mut coef_buf = Vec::<u8>::new();
+    range_provider(data.integer, &mut coef_buf);
+    let mut scale = 0;
+    if let Some(decimal) = data.decimal {
+        range_provider(decimal, &mut coef_buf);
+        scale = decimal.end - decimal.start;
+    }
+    // Only digits, see `number_data_to_term`.
+    let coef_str = unsafe { ::std::str::from_utf8_unchecked(&coef_buf) };
+    let coef = integer_to_term(env, true, coef_str);
+
+    let mut exp_buf = Vec::<u8>::new();
+    match data.exponent {
+        Some(exponent) => range_provider(exponent, &mut exp_buf),
+        None => exp_buf.push(b'0'),
+    }
+    let exp_str = unsafe { ::std::str::from_utf8_unchecked(&exp_buf) };
+
+    // exp = ±exponent - scale, done on magnitudes as the exponent can be
+    // arbitrarily long.
+    let exponent = BigUint::from_str_radix(exp_str, 10).unwrap();
+    let scale = BigUint::from_str_radix(&scale.to_string(), 10).unwrap();
+    let (exp_positive, exp) = if !data.exponent_sign {
+        (false, exponent + scale)
+    } else if exponent >= scale {
+        (true, exponent - scale)
+    } else {
+        (false, scale - exponent)
+    };
+    let exp = integer_to_term(env, exp_positive, &exp.to_str_radix(10));
+
+    let sign: i64 = if data.sign { 1 } else { -1 };
+    let fields: [(Term<'a>, Term<'a>); 4] = [
+        (
+            ::atoms::__struct__().encode(env),
+            atoms::decimal().encode(env),
+        ),
+        (atoms::sign().encode(env), sign.encode(env)),
+        (atoms::coef().encode(env), coef),
+        (atoms::exp().encode(env), exp),
+    ];
+
+    // Putting new atom keys into a map we just created can not fail.
+    let mut map = map_new(env);
+    for &(key, value) in fields.iter() {
+        map = map.map_put(key, value).unwrap_or(map);
+    }
+    map
+}
+
+/// Writes the number out the way JSON has it, without a leading `+`.
+fn push_number<F>(data: &NumberData, buf: &mut Vec<u8>, range_provider: F)
+where
+    F: Fn(Range, &mut Vec<u8>),
+{
+    if !data.sign {
+        buf.push('-' as u8);
+    }
+    range_provider(data.integer, buf);
+    if let Some(decimal) = data.decimal {
+        buf.push('.' as u8);
+        range_provider(decimal, buf);
+    }
+    if let Some(exponent) = data.exponent {
+        buf.push('e' as u8);
+        if !data.exponent_sign {
+            buf.push('-' as u8);
+        }
+        range_provider(exponent, buf);
+    }
+}
+
+/// The number as a float, for comparisons. Large integers lose
+/// precision.
+pub fn number_to_f64<F>(data: &NumberData, range_provider: F) -> f64
+where
+    F: Fn(Range, &mut Vec<u8>),
+{
+    let mut buf = Vec::<u8>::new();
+    push_number(data, &mut buf, range_provider);
+    // Only digits and the symbols added by `push_number`.
+    let num_str = unsafe { ::std::str::from_utf8_unchecked(&buf) };
+    f64::from_str(num_str).unwrap_or(::std::f64::NAN)
+}
+
+/// `floats` decides what non-integer numbers become, integers are
+/// always integers.
+pub fn number_data_to_term<F>(env: Env, data: NumberData, floats: Floats, range_provider: F) -> Term
 where
     F: Fn(Range, &mut Vec<u8>),
 {
@@ -75,29 +263,23 @@ where
 
             integer_to_term(env, data.sign, num_str)
         }
+        _ if floats == Floats::Decimal => decimal_to_term(env, &data, range_provider),
         _ => {
-            if !data.sign {
-                buf.push('-' as u8);
-            }
-            range_provider(data.integer, &mut buf);
-            if let Some(decimal) = data.decimal {
-                buf.push('.' as u8);
-                range_provider(decimal, &mut buf);
-            }
-            if let Some(exponent) = data.exponent {
-                buf.push('e' as u8);
-                if !data.exponent_sign {
-                    buf.push('-' as u8);
-                }
-                range_provider(exponent, &mut buf);
-            }
+            push_number(&data, &mut buf, range_provider);
 
             // This is safe because the tokenizer only accepts digits when reading numbers.
             // This range will thus never contain anything other than 0..9 + the symbols
             // we added.
             let num_str = unsafe { ::std::str::from_utf8_unchecked(&buf) };
 
-            float_to_term(env, num_str)
+            match floats {
+                Floats::String => match binary_from_slice(env, &buf) {
+                    Some(term) => term,
+                    // Out of memory, a float is better than nothing.
+                    None => float_to_term(env, num_str),
+                },
+                _ => float_to_term(env, num_str),
+            }
         }
     }
 }
@@ -0,0 +1,138 @@
+use rustler::types::atom::Atom;
+use rustler::types::binary::Binary;
+use rustler::types::list::ListIterator;
+use rustler::types::map::{map_new, MapIterator};
+use rustler::{Encoder, Env, Term};
+
+use std::collections::HashSet;
+
+use errors::ErrorReason;
+use options::{DuplicateKeys, Keys, MissingAtom, Objects};
+
+/// Longest atom the VM allows.
+const MAX_ATOM_BYTES: usize = 255;
+
+pub enum InsertError {
+    /// The key is already present and duplicates are not allowed.
+    Duplicate,
+    /// The term could not be built.
+    Failed,
+}
+
+/// What is kept of an object while its keys are inserted.
+#[derive(Default)]
+pub struct OpenObject {
+    /// Keys inserted so far, counting repeated ones, so that list and
+    /// map objects are limited alike.
+    pub keys: usize,
+    /// Keys of a list object, which has no index to look them up in.
+    /// Only filled when the duplicate key policy needs it.
+    seen: HashSet<Vec<u8>>,
+    /// Keys the object has in the input, left out ones included. Only
+    /// filled when its spec node has required keys or defaults.
+    pub present: HashSet<Vec<u8>>,
+}
+
+/// An empty object to insert keys into.
+pub fn new_object<'a>(env: Env<'a>, objects: Objects) -> Term<'a> {
+    match objects {
+        Objects::Maps => map_new(env),
+        Objects::OrderedLists | Objects::Proplists => {
+            let list: Vec<Term> = Vec::new();
+            list.encode(env)
+        }
+    }
+}
+
+/// Inserts a key into an object being built, following the duplicate
+/// key policy. Only looks the key up when the policy needs it. `open` is
+/// what is kept of the object, it counts the key.
+///
+/// List objects are built in reverse, with the latest pair first.
+pub fn insert<'a>(
+    env: Env<'a>,
+    open: &mut OpenObject,
+    object: Term<'a>,
+    key: Term<'a>,
+    value: Term<'a>,
+    duplicates: DuplicateKeys,
+) -> Result<Term<'a>, InsertError> {
+    open.keys += 1;
+    let duplicate = match duplicates {
+        DuplicateKeys::Last => false,
+        _ if object.is_map() => object.map_get(key).is_ok(),
+        _ => !open.seen.insert(key_bytes(key)),
+    };
+    if duplicate {
+        return match duplicates {
+            DuplicateKeys::First => Ok(object),
+            _ => Err(InsertError::Duplicate),
+        };
+    }
+
+    if object.is_map() {
+        object.map_put(key, value).map_err(|_| InsertError::Failed)
+    } else {
+        Ok(object.list_prepend((key, value).encode(env)))
+    }
+}
+
+/// Called once all keys of an object have been inserted. Returns `None`
+/// if the term could not be built.
+pub fn finish<'a>(object: Term<'a>, objects: Objects) -> Option<Term<'a>> {
+    match objects {
+        Objects::OrderedLists => object.list_reverse().ok(),
+        Objects::Maps | Objects::Proplists => Some(object),
+    }
+}
+
+/// Looks up a key in a finished object by its bytes, so atom keys are
+/// found as well. Linear in the size of the object.
+pub fn get<'a>(object: Term<'a>, key: &[u8]) -> Option<Term<'a>> {
+    if object.is_map() {
+        MapIterator::new(object)?
+            .find(|&(existing, _)| key_bytes(existing) == key)
+            .map(|(_, value)| value)
+    } else {
+        let iterator: ListIterator = object.decode().ok()?;
+        iterator
+            .filter_map(|pair| pair.decode::<(Term, Term)>().ok())
+            .find(|&(existing, _)| key_bytes(existing) == key)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Bytes of an object key, which is either a binary or an atom.
+pub fn key_bytes(key: Term) -> Vec<u8> {
+    match key.decode::<Binary>() {
+        Ok(bin) => bin.as_slice().to_vec(),
+        Err(_) => key.atom_to_string().unwrap_or_default().into_bytes(),
+    }
+}
+
+/// Looks up the atom an object key should decode to. `Ok(None)` means
+/// the key is kept as a binary.
+///
+/// Atoms are created as latin1, so keys that are not plain ASCII are
+/// never turned into atoms.
+pub fn key_atom(env: Env, key: &[u8], keys: &Keys) -> Result<Option<Atom>, ErrorReason> {
+    let representable = key.len() <= MAX_ATOM_BYTES && key.is_ascii();
+    match keys {
+        &Keys::Strings => Ok(None),
+        &Keys::Atoms if representable => Ok(Atom::from_bytes(env, key).ok()),
+        &Keys::Atoms => Ok(None),
+        &Keys::ExistingAtoms(missing) => {
+            let existing = if representable {
+                Atom::try_from_bytes(env, key).ok().and_then(|atom| atom)
+            } else {
+                None
+            };
+            match (existing, missing) {
+                (Some(atom), _) => Ok(Some(atom)),
+                (None, MissingAtom::Binary) => Ok(None),
+                (None, MissingAtom::Error) => Err(ErrorReason::MissingAtom(key.to_vec())),
+            }
+        }
+        &Keys::Intern(ref table) => Ok(table.get(key).cloned()),
+    }
+}
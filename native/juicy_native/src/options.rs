@@ -0,0 +1,315 @@
+use rustler::types::atom::Atom;
+use rustler::types::list::ListIterator;
+use rustler::{Decoder, Error, NifResult, Term};
+use std::collections::HashMap;
+
+mod atoms {
+    atoms! {
+        duplicate_keys,
+        last,
+        first,
+        error,
+        keys,
+        strings,
+        atoms,
+        existing_atoms,
+        intern,
+        binary,
+        objects,
+        maps,
+        ordered_lists,
+        proplists,
+        max_depth,
+        max_string_bytes,
+        max_number_digits,
+        max_keys_per_object,
+        max_total_bytes,
+        floats,
+        native,
+        decimal,
+        string,
+        documents,
+        single,
+        lines,
+        concatenated,
+        json_seq,
+        skip_invalid,
+    }
+}
+
+/// What to do when an object contains the same key more than once.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// The last value wins.
+    Last,
+    /// The first value wins.
+    First,
+    /// Duplicates are a parse error.
+    Error,
+}
+
+impl<'a> Decoder<'a> for DuplicateKeys {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if atoms::last() == term {
+            Ok(DuplicateKeys::Last)
+        } else if atoms::first() == term {
+            Ok(DuplicateKeys::First)
+        } else if atoms::error() == term {
+            Ok(DuplicateKeys::Error)
+        } else {
+            Err(Error::BadArg)
+        }
+    }
+}
+
+/// What JSON objects are decoded into.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Objects {
+    Maps,
+    /// `[{key, value}]` in document order.
+    OrderedLists,
+    /// `[{key, value}]` with the last pair in the document first.
+    Proplists,
+}
+
+impl<'a> Decoder<'a> for Objects {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if atoms::maps() == term {
+            Ok(Objects::Maps)
+        } else if atoms::ordered_lists() == term {
+            Ok(Objects::OrderedLists)
+        } else if atoms::proplists() == term {
+            Ok(Objects::Proplists)
+        } else {
+            Err(Error::BadArg)
+        }
+    }
+}
+
+/// What numbers with a fraction or exponent are decoded into.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Floats {
+    Native,
+    /// A `%Decimal{}` struct, built without going through a float.
+    Decimal,
+    /// A binary with the number as written.
+    String,
+}
+
+impl<'a> Decoder<'a> for Floats {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if atoms::native() == term {
+            Ok(Floats::Native)
+        } else if atoms::decimal() == term {
+            Ok(Floats::Decimal)
+        } else if atoms::string() == term {
+            Ok(Floats::String)
+        } else {
+            Err(Error::BadArg)
+        }
+    }
+}
+
+/// Starts every record of a JSON text sequence, RFC 7464.
+pub const RECORD_SEPARATOR: u8 = 0x1E;
+
+/// How the documents of a stream are laid out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Documents {
+    Single,
+    /// One document per line, as in NDJSON and JSON Lines. Documents
+    /// can not span lines, blank lines are skipped.
+    Lines,
+    /// Documents one after the other, with optional whitespace between.
+    Concatenated,
+    /// A JSON text sequence, RFC 7464. Each document is preceded by a
+    /// record separator and followed by a newline.
+    Sequence,
+}
+
+impl Documents {
+    /// Whether `byte` ends any document it is found in. The parser sees
+    /// it as the end of the input.
+    pub fn ends_document(self, byte: u8) -> bool {
+        match (self, byte) {
+            (Documents::Lines, b'\n') => true,
+            (Documents::Sequence, RECORD_SEPARATOR) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for Documents {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if atoms::single() == term {
+            Ok(Documents::Single)
+        } else if atoms::lines() == term {
+            Ok(Documents::Lines)
+        } else if atoms::concatenated() == term {
+            Ok(Documents::Concatenated)
+        } else if atoms::json_seq() == term {
+            Ok(Documents::Sequence)
+        } else {
+            Err(Error::BadArg)
+        }
+    }
+}
+
+/// How object keys are decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Keys {
+    Strings,
+    Atoms,
+    /// Only atoms that already exist are used, never creating new ones.
+    ExistingAtoms(MissingAtom),
+    /// Keys found in the table become atoms, others stay binaries.
+    Intern(HashMap<Vec<u8>, Atom>),
+}
+
+/// What to do with a key that has no existing atom.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MissingAtom {
+    Binary,
+    Error,
+}
+
+impl<'a> Decoder<'a> for Keys {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if atoms::strings() == term {
+            return Ok(Keys::Strings);
+        } else if atoms::atoms() == term {
+            return Ok(Keys::Atoms);
+        } else if atoms::existing_atoms() == term {
+            return Ok(Keys::ExistingAtoms(MissingAtom::Binary));
+        }
+
+        let (mode, data): (Term, Term) = term.decode()?;
+        if atoms::existing_atoms() == mode {
+            if atoms::binary() == data {
+                Ok(Keys::ExistingAtoms(MissingAtom::Binary))
+            } else if atoms::error() == data {
+                Ok(Keys::ExistingAtoms(MissingAtom::Error))
+            } else {
+                Err(Error::BadArg)
+            }
+        } else if atoms::intern() == mode {
+            let mut table: HashMap<Vec<u8>, Atom> = HashMap::new();
+            let iterator: ListIterator = data.decode()?;
+            for atom_term in iterator {
+                let atom_str: String = atom_term.atom_to_string()?;
+                let atom: Atom = atom_term.decode()?;
+                table.insert(atom_str.into_bytes(), atom);
+            }
+            Ok(Keys::Intern(table))
+        } else {
+            Err(Error::BadArg)
+        }
+    }
+}
+
+/// A limit that was exceeded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    Depth,
+    StringBytes,
+    NumberDigits,
+    KeysPerObject,
+    TotalBytes,
+}
+
+/// Limits on the size of the input, protecting against documents that
+/// would use an unreasonable amount of memory. `None` is unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub max_depth: Option<usize>,
+    pub max_string_bytes: Option<usize>,
+    pub max_number_digits: Option<usize>,
+    pub max_keys_per_object: Option<usize>,
+    pub max_total_bytes: Option<usize>,
+}
+
+impl Limits {
+    pub fn depth(&self, depth: usize) -> Result<(), Limit> {
+        within(self.max_depth, depth, Limit::Depth)
+    }
+    pub fn string_bytes(&self, bytes: usize) -> Result<(), Limit> {
+        within(self.max_string_bytes, bytes, Limit::StringBytes)
+    }
+    pub fn number_digits(&self, digits: usize) -> Result<(), Limit> {
+        within(self.max_number_digits, digits, Limit::NumberDigits)
+    }
+    pub fn keys_per_object(&self, keys: usize) -> Result<(), Limit> {
+        within(self.max_keys_per_object, keys, Limit::KeysPerObject)
+    }
+    /// `position` is the offset of the byte about to be read.
+    pub fn total_bytes(&self, position: usize) -> Result<(), Limit> {
+        within(self.max_total_bytes, position + 1, Limit::TotalBytes)
+    }
+}
+
+fn within(max: Option<usize>, value: usize, limit: Limit) -> Result<(), Limit> {
+    match max {
+        Some(max) if value > max => Err(limit),
+        _ => Ok(()),
+    }
+}
+
+/// Options given to a parse call, as opposed to options on spec nodes.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    pub duplicate_keys: DuplicateKeys,
+    pub keys: Keys,
+    pub objects: Objects,
+    pub floats: Floats,
+    pub limits: Limits,
+    /// Only read by streaming parses.
+    pub documents: Documents,
+    /// Whether a stream of several documents goes on after one fails.
+    pub skip_invalid: bool,
+}
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            duplicate_keys: DuplicateKeys::Last,
+            keys: Keys::Strings,
+            objects: Objects::Maps,
+            floats: Floats::Native,
+            limits: Limits::default(),
+            documents: Documents::Single,
+            skip_invalid: false,
+        }
+    }
+}
+
+pub fn read_parse_opts(term: Term) -> NifResult<ParseOptions> {
+    let iterator: ListIterator = term.decode()?;
+    let mut opts = ParseOptions::default();
+    for decoded in iterator.map(|term| term.decode::<(Term, Term)>()) {
+        let (key, value) = decoded?;
+
+        if atoms::duplicate_keys() == key {
+            opts.duplicate_keys = value.decode()?;
+        } else if atoms::keys() == key {
+            opts.keys = value.decode()?;
+        } else if atoms::objects() == key {
+            opts.objects = value.decode()?;
+        } else if atoms::floats() == key {
+            opts.floats = value.decode()?;
+        } else if atoms::max_depth() == key {
+            opts.limits.max_depth = Some(value.decode()?);
+        } else if atoms::max_string_bytes() == key {
+            opts.limits.max_string_bytes = Some(value.decode()?);
+        } else if atoms::max_number_digits() == key {
+            opts.limits.max_number_digits = Some(value.decode()?);
+        } else if atoms::max_keys_per_object() == key {
+            opts.limits.max_keys_per_object = Some(value.decode()?);
+        } else if atoms::max_total_bytes() == key {
+            opts.limits.max_total_bytes = Some(value.decode()?);
+        } else if atoms::documents() == key {
+            opts.documents = value.decode()?;
+        } else if atoms::skip_invalid() == key {
+            opts.skip_invalid = value.decode()?;
+        }
+    }
+    Ok(opts)
+}
@@ -1,5 +1,6 @@
+use errors::{push_pointer_index, push_pointer_key};
 use iterative_json_parser::Position;
-use tree_spec::{NodeId, PathEntry, PathPosition, SpecWalker, ValueType};
+use tree_spec::{NodeId, PathEntry, PathPosition, SpecError, SpecWalker, ValueType};
 
 pub struct PathTracker {
     pub path: Vec<PathEntry>,
@@ -7,23 +8,33 @@ pub struct PathTracker {
 }
 
 impl PathTracker {
-    pub fn visit_terminal(&mut self, _pos: Position, typ: ValueType) -> PathPosition {
-        let path_pos = self.walker.visit_terminal(typ, self.path.last());
+    pub fn visit_terminal(
+        &mut self,
+        _pos: Position,
+        typ: ValueType,
+    ) -> Result<PathPosition, SpecError> {
+        self.walker.visit_terminal(typ, self.path.last())
+    }
+
+    /// Moves the path past a terminal once it has been handled. Until
+    /// then the path still points at it, which is what errors want.
+    pub fn exit_terminal(&mut self) {
         self.update_path();
-        path_pos
     }
 
-    pub fn enter_array(&mut self, _pos: Position) {
+    pub fn enter_array(&mut self, _pos: Position) -> Result<(), SpecError> {
         {
             let last_key = self.path.last();
-            self.walker.enter_nonterminal(ValueType::Array, last_key);
+            self.walker.enter_nonterminal(ValueType::Array, last_key)?;
         }
         self.path.push(PathEntry::Index(0));
+        Ok(())
     }
 
-    pub fn enter_map(&mut self, _pos: Position) {
+    pub fn enter_map(&mut self, _pos: Position) -> Result<(), SpecError> {
         let last_key = self.path.last();
-        self.walker.enter_nonterminal(ValueType::Object, last_key);
+        self.walker.enter_nonterminal(ValueType::Object, last_key)?;
+        Ok(())
     }
 
     pub fn exit_array(&mut self) -> PathPosition {
@@ -44,6 +55,20 @@ impl PathTracker {
         self.walker.visit_key()
     }
 
+    /// JSON Pointer to the value currently being parsed.
+    pub fn json_pointer(&self) -> String {
+        pointer_of(&self.path)
+    }
+
+    /// JSON Pointer to the innermost container, for errors about the
+    /// container as a whole.
+    pub fn container_pointer(&self) -> String {
+        match self.path.last() {
+            Some(&PathEntry::Index(_)) => pointer_of(&self.path[..self.path.len() - 1]),
+            _ => pointer_of(&self.path),
+        }
+    }
+
     fn update_path(&mut self) {
         match self.path.pop() {
             Some(PathEntry::Index(index)) => self.path.push(PathEntry::Index(index + 1)),
@@ -52,3 +77,16 @@ impl PathTracker {
         }
     }
 }
+
+fn pointer_of(path: &[PathEntry]) -> String {
+    let mut pointer = String::new();
+    for entry in path.iter() {
+        match entry {
+            // Unlike when yielding, the index here has not been
+            // advanced past the current value yet.
+            &PathEntry::Index(index) => push_pointer_index(&mut pointer, index),
+            &PathEntry::Key(ref key) => push_pointer_key(&mut pointer, key),
+        }
+    }
+    pointer
+}
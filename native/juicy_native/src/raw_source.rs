@@ -0,0 +1,149 @@
+//! Locates the source text of values for `value` nodes with `raw: true`.
+//!
+//! The parser reports values through the sink without their extent in
+//! the input, except for the digits of numbers. For other values it is
+//! worked out from the bytes around the position at which a callback
+//! happens. By then the parser has validated the value, which keeps the
+//! scanning here simple.
+//!
+//! `byte` returns `None` for positions past the available input.
+
+use iterative_json_parser::{NumberData, Range};
+
+fn is_whitespace(byte: u8) -> bool {
+    match byte {
+        b' ' | b'\t' | b'\n' | b'\r' => true,
+        _ => false,
+    }
+}
+
+fn is_scalar(byte: u8) -> bool {
+    match byte {
+        b'0'..=b'9' | b'a'..=b'z' | b'+' | b'-' | b'.' | b'E' => true,
+        _ => false,
+    }
+}
+
+fn before<F>(byte: &F, pos: usize) -> Option<u8>
+where
+    F: Fn(usize) -> Option<u8>,
+{
+    if pos == 0 {
+        None
+    } else {
+        byte(pos - 1)
+    }
+}
+
+fn skip_whitespace<F>(byte: &F, mut pos: usize) -> usize
+where
+    F: Fn(usize) -> Option<u8>,
+{
+    while byte(pos).map(is_whitespace).unwrap_or(false) {
+        pos += 1;
+    }
+    pos
+}
+
+/// Start of a container reported at `pos`. `opener_consumed` tells if
+/// the parser had consumed the bracket by the time of the callback.
+pub fn container_start<F>(byte: F, pos: usize, opener_consumed: bool) -> usize
+where
+    F: Fn(usize) -> Option<u8>,
+{
+    if opener_consumed {
+        pos - 1
+    } else {
+        skip_whitespace(&byte, pos)
+    }
+}
+
+/// Whether the parser had consumed the bracket of the root container,
+/// reported at `pos`, by the time of the callback. Nothing but
+/// whitespace can come before the root, so this is exact there and is
+/// taken to hold for all containers.
+pub fn opener_consumed<F>(byte: F, pos: usize) -> bool
+where
+    F: Fn(usize) -> Option<u8>,
+{
+    match before(&byte, pos) {
+        Some(b'{') | Some(b'[') => true,
+        _ => false,
+    }
+}
+
+/// Start of a string whose `start_string` callback happened at `pos`.
+/// A string value can not directly follow a quote, so a quote before
+/// `pos` is its own.
+pub fn string_start<F>(byte: F, pos: usize) -> usize
+where
+    F: Fn(usize) -> Option<u8>,
+{
+    match before(&byte, pos) {
+        Some(b'"') => pos - 1,
+        _ => skip_whitespace(&byte, pos),
+    }
+}
+
+/// Source of a number, from the ranges of its digits.
+pub fn number_range(number: &NumberData) -> Range {
+    let start = if number.sign {
+        number.integer.start
+    } else {
+        // The minus sign comes right before the digits.
+        number.integer.start - 1
+    };
+    let last = number.exponent.or(number.decimal).unwrap_or(number.integer);
+    Range {
+        start: start,
+        end: last.end,
+    }
+}
+
+/// Start of `true`, `false` or `null` reported at `pos`.
+pub fn scalar_start<F>(byte: F, pos: usize) -> usize
+where
+    F: Fn(usize) -> Option<u8>,
+{
+    let mut start = pos;
+    while before(&byte, start).map(is_scalar).unwrap_or(false) {
+        start -= 1;
+    }
+    if start == pos {
+        skip_whitespace(&byte, pos)
+    } else {
+        start
+    }
+}
+
+/// End of the value starting at `start`, exclusive.
+pub fn value_end<F>(byte: F, start: usize) -> usize
+where
+    F: Fn(usize) -> Option<u8>,
+{
+    let mut pos = start;
+    let mut depth = 0;
+    let mut in_string = false;
+
+    while let Some(current) = byte(pos) {
+        pos += 1;
+        if in_string {
+            match current {
+                b'\\' => pos += 1,
+                b'"' => in_string = false,
+                _ => (),
+            }
+        } else {
+            match current {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => depth -= 1,
+                _ => (),
+            }
+        }
+        if depth == 0 && !in_string && !byte(pos).map(is_scalar).unwrap_or(false) {
+            return pos;
+        }
+    }
+    pos
+}
@@ -0,0 +1,1110 @@
+//! The sink used to decode against a spec, by `parse_spec` on a single
+//! binary and by streaming parses on chunks of input. What only applies
+//! to streams, yielding values and splitting documents, is left out of
+//! single binary parses.
+
+use errors::{push_pointer_key, DecodeError, ErrorReason};
+use numbers::{number_data_to_term, number_digits, number_to_f64};
+use objects::{finish, get, insert, key_bytes, new_object, InsertError, OpenObject};
+use options::{Documents, Limit, ParseOptions};
+use strings::{binary_from_slice, BuildString};
+
+use tree_spec::{
+    Constraints, NodeId, NodeOptions, NodeVariant, PathPosition, SpecError, StreamCollect,
+    TagDefault,
+};
+use tree_spec::{SpecWalker, ValueType};
+
+use rustler::types::binary::Binary;
+use rustler::{Encoder, Env, Term};
+
+use iterative_json_parser::Range as PRange;
+use iterative_json_parser::{
+    Bailable, NumberData, PeekResult, Pos, Position, Sink, Source, StringPosition,
+};
+
+use input_provider::streaming::{StreamingInputProvider, StreamingInputResult};
+use input_provider::InputProvider;
+
+use basic_spec::parse_node;
+use path_tracker::PathTracker;
+use raw_source;
+use spec_terms::{complete_object, finish_array, finish_enum, key_term};
+
+#[derive(Debug, Copy, Clone)]
+pub enum BailType {
+    Reschedule,
+    AwaitInput,
+    /// The sink failed, the error is stored in the sink.
+    Error,
+    /// The outermost value of a document in a stream of several has
+    /// been parsed.
+    DocumentEnd,
+}
+
+pub struct StreamingSS<'a, 'b>
+where
+    'a: 'b,
+{
+    pub env: Env<'a>,
+    pub input: StreamingInputProvider<'a, 'b>,
+    pub next_reschedule: usize,
+    pub out_stack: Vec<Term<'a>>,
+    pub state: &'b mut SSState,
+    /// Values of `stream` nodes, yielded as they are parsed. `None` when
+    /// the whole document is returned at once, with streamed values left
+    /// in place.
+    pub yields: Option<Vec<Term<'a>>>,
+    /// Set when the sink fails. The parser is stopped with a bail at the
+    /// next opportunity.
+    pub error: Option<DecodeError>,
+}
+
+/// Where a stream of several documents is at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DocumentState {
+    /// Skipping whitespace before the next document.
+    Between,
+    Parsing,
+    /// Set by the sink once the outermost value ends. The parser is
+    /// stopped at the next opportunity.
+    Parsed,
+    /// After a document, until it is known to be complete.
+    Ended,
+    /// After the record separator of a JSON text sequence.
+    Separated,
+    /// Skipping the rest of the line or record of a document that
+    /// failed.
+    Discarding,
+}
+
+pub struct SSState {
+    pub path_tracker: PathTracker,
+
+    pub position: usize,
+    pub first_needed: usize,
+    pub current_string: BuildString,
+
+    pub options: ParseOptions,
+    /// Number of containers enclosing the current position.
+    pub depth: usize,
+
+    /// Position of the `start_string` callback of the current string.
+    pub string_start: usize,
+    /// Start of the open container whose source is needed once it ends,
+    /// on a `value` node with `raw: true` or a `tagged_map` node.
+    /// Input from here on is kept until the container ends.
+    pub source_start: Option<usize>,
+    /// Line of `source_start` and where that line starts.
+    pub source_line: (usize, usize),
+    /// Whether the parser consumes a bracket before reporting the
+    /// container, found out at the root container.
+    pub opener_consumed: Option<bool>,
+    /// Values that failed the constraints of their nodes, with their
+    /// paths. Reported together once the document ends.
+    pub violations: Vec<(ErrorReason, String)>,
+    /// Positions on the out stack of keys whose pairs are left out of
+    /// their object.
+    pub ignored_keys: Vec<usize>,
+    /// Depth of the container whose value is skipped, for `ignore` nodes
+    /// and keys left out of their object. No terms are built while set.
+    pub skip_from: Option<usize>,
+    /// Set by a key that is left out, so that its value is skipped.
+    pub skip_next: bool,
+    /// One for every object that is built, innermost last.
+    pub objects: Vec<OpenObject>,
+    /// Set when a streamed value is left out of its container, until
+    /// the container takes it.
+    pub value_dropped: bool,
+    /// One for every container on the spec that is built while
+    /// streaming, innermost last.
+    pub streamed: Vec<Streamed>,
+
+    /// Line of `position`, 1-based. Tracked as we go since earlier
+    /// input is released once it is no longer needed.
+    pub line: usize,
+    pub line_start: usize,
+
+    /// Always `Parsing` for a single document.
+    pub document: DocumentState,
+    /// Line the current document starts on.
+    pub document_line: usize,
+    /// Number of documents started so far, including ones that failed.
+    pub document_count: usize,
+    /// Where the input ends, once the stream has ended.
+    pub input_end: Option<usize>,
+}
+
+/// Streamed children a container left out, so that its checks count
+/// them as present.
+#[derive(Default)]
+pub struct Streamed {
+    pub count: usize,
+}
+
+impl SSState {
+    /// State for a single document whose outermost value is at
+    /// `path_tracker`, inside `depth` containers.
+    pub fn new(path_tracker: PathTracker, options: ParseOptions, depth: usize) -> SSState {
+        SSState {
+            path_tracker: path_tracker,
+
+            position: 0,
+            first_needed: 0,
+            current_string: BuildString::None,
+
+            options: options,
+            depth: depth,
+            string_start: 0,
+            source_start: None,
+            source_line: (1, 0),
+            opener_consumed: None,
+            violations: Vec::new(),
+            ignored_keys: Vec::new(),
+            skip_from: None,
+            skip_next: false,
+            objects: Vec::new(),
+            value_dropped: false,
+            streamed: Vec::new(),
+
+            line: 1,
+            line_start: 0,
+
+            document: DocumentState::Parsing,
+            document_line: 1,
+            document_count: 0,
+            input_end: None,
+        }
+    }
+
+    /// The first position of input that has to be kept for the next
+    /// iteration.
+    pub fn needed_from(&self) -> usize {
+        match self.source_start {
+            Some(start) if start < self.first_needed => start,
+            _ => self.first_needed,
+        }
+    }
+
+    /// Forgets what is left of the previous document before the next
+    /// one starts.
+    pub fn reset_document(&mut self) {
+        let spec = self.path_tracker.walker.spec.clone();
+        self.path_tracker = PathTracker {
+            path: Vec::new(),
+            walker: SpecWalker::new(spec),
+        };
+        self.current_string = BuildString::None;
+        self.depth = 0;
+        self.source_start = None;
+        self.violations.clear();
+        self.ignored_keys.clear();
+        self.skip_from = None;
+        self.skip_next = false;
+        self.objects.clear();
+        self.value_dropped = false;
+        self.streamed.clear();
+        self.document = DocumentState::Parsing;
+        self.document_line = self.line;
+        self.document_count += 1;
+    }
+}
+
+impl<'a, 'b> Bailable for StreamingSS<'a, 'b> {
+    type Bail = BailType;
+}
+
+impl<'a, 'b> Source for StreamingSS<'a, 'b> {
+    fn position(&self) -> Pos {
+        self.state.position.into()
+    }
+    fn skip(&mut self, num: usize) {
+        for pos in self.state.position..(self.state.position + num) {
+            if let StreamingInputResult::Ok(b'\n') = self.input.byte(pos) {
+                self.state.line += 1;
+                self.state.line_start = pos + 1;
+            }
+        }
+        self.state.position += num
+    }
+    fn peek_char(&mut self) -> PeekResult<BailType> {
+        if self.error.is_some() {
+            PeekResult::Bail(BailType::Error)
+        } else if self.state.document == DocumentState::Parsed {
+            PeekResult::Bail(BailType::DocumentEnd)
+        } else if self.state.position >= self.next_reschedule {
+            PeekResult::Bail(BailType::Reschedule)
+        } else {
+            match self.input.byte(self.state.position) {
+                StreamingInputResult::Ok(byte)
+                    if self.state.options.documents.ends_document(byte) =>
+                {
+                    PeekResult::Eof
+                }
+                StreamingInputResult::Ok(byte) => {
+                    let result = self.state.options.limits.total_bytes(self.state.position);
+                    match self.check_limit(result) {
+                        Ok(()) => PeekResult::Ok(byte),
+                        Err(bail) => PeekResult::Bail(bail),
+                    }
+                }
+                StreamingInputResult::AwaitInput => PeekResult::Bail(BailType::AwaitInput),
+                StreamingInputResult::Eof => PeekResult::Eof,
+            }
+        }
+    }
+    fn peek_slice<'c>(&'c self, _length: usize) -> Option<&'c [u8]> {
+        None
+    }
+}
+
+impl<'a, 'b> StreamingSS<'a, 'b> {
+    fn fail(&mut self, reason: ErrorReason) -> Result<(), BailType> {
+        let path = self.state.path_tracker.json_pointer();
+        self.fail_at(reason, path)
+    }
+
+    fn fail_at(&mut self, reason: ErrorReason, path: String) -> Result<(), BailType> {
+        if self.error.is_none() {
+            self.error = Some(DecodeError::new(reason, self.state.position, path));
+        }
+        Err(BailType::Error)
+    }
+
+    fn fail_internal(&mut self, message: &str) -> Result<(), BailType> {
+        self.fail(ErrorReason::Internal(message.to_string()))
+    }
+
+    fn check_limit(&mut self, result: Result<(), Limit>) -> Result<(), BailType> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(limit) => self.fail(ErrorReason::LimitExceeded(limit)),
+        }
+    }
+
+    fn check_string_len(&mut self) {
+        let result = self
+            .state
+            .options
+            .limits
+            .string_bytes(self.state.current_string.len());
+        let _ = self.check_limit(result);
+    }
+
+    fn enter_container(&mut self) {
+        self.state.depth += 1;
+        let result = self.state.options.limits.depth(self.state.depth);
+        let _ = self.check_limit(result);
+    }
+
+    /// Options of the innermost spec node enclosing the current position.
+    fn nearest_options(&self) -> &NodeOptions {
+        let walker = &self.state.path_tracker.walker;
+        &walker.spec.get(walker.nearest()).options
+    }
+
+    /// Options that apply to a terminal, those of its own node if it is
+    /// on the spec.
+    fn terminal_options(&self, path_pos: &PathPosition) -> &NodeOptions {
+        match path_pos.current {
+            Some(node_id) => &self.state.path_tracker.walker.spec.get(node_id).options,
+            None => self.nearest_options(),
+        }
+    }
+
+    /// Constraints of the node of a terminal. Unlike options, they are
+    /// not inherited.
+    fn terminal_constraints(&self, path_pos: &PathPosition) -> Option<&Constraints> {
+        path_pos.current.map(|node_id| {
+            &self
+                .state
+                .path_tracker
+                .walker
+                .spec
+                .get(node_id)
+                .options
+                .constraints
+        })
+    }
+
+    /// Called while the path is still at the terminal.
+    fn add_violations(&mut self, reasons: Vec<ErrorReason>) {
+        for reason in reasons {
+            let path = self.state.path_tracker.json_pointer();
+            self.state.violations.push((reason, path));
+        }
+    }
+
+    /// Called once a value has been handled. Stops the parser after the
+    /// outermost one when the stream holds several documents.
+    fn end_value(&mut self) {
+        if self.state.depth == 0 && self.state.options.documents != Documents::Single {
+            self.state.document = DocumentState::Parsed;
+        }
+    }
+
+    /// Whether a value starting on `node_id` is skipped, for an `ignore`
+    /// node or a key that was left out. Not called while skipping.
+    fn take_skip(&mut self, node_id: Option<NodeId>) -> bool {
+        let left_out = ::std::mem::replace(&mut self.state.skip_next, false);
+        let ignored = match node_id {
+            Some(node_id) => self.state.path_tracker.walker.spec.get(node_id).is_ignore(),
+            None => false,
+        };
+        left_out || ignored
+    }
+
+    /// Called once a container has been entered. Whether it is skipped,
+    /// starting the skip if it is the skipped value.
+    fn skip_container(&mut self) -> bool {
+        if self.state.skip_from.is_some() {
+            return true;
+        }
+        let node_id = self.state.path_tracker.walker.container();
+        if self.take_skip(node_id) {
+            self.state.skip_from = Some(self.state.depth);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Called at the end of a skipped container, once the depth no longer
+    /// counts it. A placeholder takes the place of the skipped value.
+    fn exit_skipped(&mut self) {
+        if self.state.skip_from == Some(self.state.depth + 1) {
+            self.state.skip_from = None;
+            self.out_stack.push(::atoms::nil().encode(self.env));
+        }
+        self.state.first_needed = self.state.position;
+        self.end_value();
+    }
+
+    /// Moves the path past a terminal if it is skipped.
+    fn skip_terminal(&mut self, path_pos: &PathPosition) -> bool {
+        let inside = self.state.skip_from.is_some();
+        if !inside && !self.take_skip(path_pos.current) {
+            return false;
+        }
+        if !inside {
+            self.out_stack.push(::atoms::nil().encode(self.env));
+        }
+        self.state.path_tracker.exit_terminal();
+        self.state.first_needed = self.state.position;
+        self.end_value();
+        true
+    }
+
+    /// Pushes the term for a key of an object on `node_id`, or a
+    /// placeholder if the key is left out along with its value.
+    fn push_key(&mut self, node_id: Option<NodeId>, key: &[u8]) -> Result<(), BailType> {
+        let spec = self.state.path_tracker.walker.spec.clone();
+        let node = node_id.map(|node_id| spec.get(node_id));
+        let skipped = match node {
+            Some(node) => match node.variant.child_key(key) {
+                Ok(Some(child)) => spec.get(child).is_ignore(),
+                Ok(None) => node.options.ignore_unknown,
+                Err(_) => false,
+            },
+            None => false,
+        };
+        let tracked = node.map_or(false, |node| {
+            !node.options.required.is_empty() || !node.options.defaults.is_empty()
+        });
+        if tracked {
+            let open = self.state.objects.last_mut().unwrap();
+            open.present.insert(key.to_vec());
+        }
+
+        let term = if skipped {
+            Ok(None)
+        } else {
+            key_term(self.env, node.map(|node| &node.options), key)
+        };
+        match term {
+            Ok(Some(term)) => self.out_stack.push(term),
+            Ok(None) => {
+                // Holds the place of the key until the pair is dropped in
+                // `pop_into_map`.
+                self.state.ignored_keys.push(self.out_stack.len());
+                self.out_stack.push(::atoms::nil().encode(self.env));
+                self.state.skip_next = true;
+            }
+            Err(reason) => return self.fail(reason),
+        }
+        Ok(())
+    }
+
+    /// Called once the value has been parsed, so the key is no longer on
+    /// the path.
+    fn fail_duplicate_key(&mut self, key: Term<'a>) -> Result<(), BailType> {
+        let key = key_bytes(key);
+        let mut path = self.state.path_tracker.json_pointer();
+        push_pointer_key(&mut path, &key);
+        self.fail_at(ErrorReason::DuplicateKey(key), path)
+    }
+
+    fn visit_terminal(&mut self, pos: Position, typ: ValueType) -> Result<PathPosition, BailType> {
+        match self.state.path_tracker.visit_terminal(pos, typ) {
+            Ok(path_pos) => Ok(path_pos),
+            Err(error) => {
+                let _ = self.fail(ErrorReason::Spec(error));
+                Err(BailType::Error)
+            }
+        }
+    }
+
+    /// Pushes a finished terminal and moves the path past it. `source`
+    /// is where the terminal is in the input, if the parser told.
+    fn push_terminal(
+        &mut self,
+        path_pos: &PathPosition,
+        typ: ValueType,
+        term: Term<'a>,
+        source: Option<PRange>,
+    ) -> Result<(), BailType> {
+        self.out_stack.push(term);
+        self.check_type(path_pos.current, typ);
+        self.finish_non_string(path_pos.current, typ)?;
+        self.replace_value(path_pos.current, typ, source)?;
+        self.state.path_tracker.exit_terminal();
+        self.do_stream(path_pos.current)?;
+        self.state.first_needed = self.state.position;
+        self.end_value();
+        Ok(())
+    }
+
+    /// Pointer to a value while the path is still in it. The path of an
+    /// array holds the index of its next element.
+    fn value_pointer(&self, typ: ValueType) -> String {
+        match typ {
+            ValueType::Array => self.state.path_tracker.container_pointer(),
+            _ => self.state.path_tracker.json_pointer(),
+        }
+    }
+
+    /// Called while the path is still in the value.
+    fn check_type(&mut self, node_id: Option<NodeId>, typ: ValueType) {
+        let violation = node_id.and_then(|node_id| {
+            let node = self.state.path_tracker.walker.spec.get(node_id);
+            node.options.constraints.check_type(typ)
+        });
+        if let Some(reason) = violation {
+            let path = self.value_pointer(typ);
+            self.state.violations.push((reason, path));
+        }
+    }
+
+    /// Values other than strings on an `enum` node take its default.
+    /// Called with the value on top of the stack, while the path still
+    /// points at it.
+    fn finish_non_string(
+        &mut self,
+        node_id: Option<NodeId>,
+        typ: ValueType,
+    ) -> Result<(), BailType> {
+        let spec = self.state.path_tracker.walker.spec.clone();
+        let node = match node_id {
+            Some(node_id) => spec.get(node_id),
+            None => return Ok(()),
+        };
+        if typ == ValueType::String || !node.is_enum() {
+            return Ok(());
+        }
+        match finish_enum(self.env, node, None) {
+            Ok(term) => {
+                self.out_stack.pop();
+                self.out_stack.push(term);
+                Ok(())
+            }
+            Err(reason) => {
+                let path = self.value_pointer(typ);
+                self.fail_at(reason, path)
+            }
+        }
+    }
+
+    fn input_byte(&self, pos: usize) -> Option<u8> {
+        match self.input.byte(pos) {
+            StreamingInputResult::Ok(byte) => Some(byte),
+            _ => None,
+        }
+    }
+
+    /// Called once a container has been entered. Records where it starts
+    /// if its source is needed.
+    fn enter_source(&mut self) {
+        let position = self.state.position;
+        let consumed = match self.state.opener_consumed {
+            Some(consumed) => consumed,
+            None => {
+                let consumed = raw_source::opener_consumed(|pos| self.input_byte(pos), position);
+                self.state.opener_consumed = Some(consumed);
+                consumed
+            }
+        };
+
+        let needed = match self.state.path_tracker.walker.container() {
+            Some(node_id) => match self.state.path_tracker.walker.spec.get(node_id).variant {
+                NodeVariant::Value { raw } => raw,
+                NodeVariant::TaggedMap { .. } => true,
+                _ => false,
+            },
+            None => false,
+        };
+        if needed {
+            let start = raw_source::container_start(|pos| self.input_byte(pos), position, consumed);
+            // Whitespace before the bracket may not have been counted yet.
+            let (mut line, mut line_start) = (self.state.line, self.state.line_start);
+            for pos in position..start {
+                if self.input_byte(pos) == Some(b'\n') {
+                    line += 1;
+                    line_start = pos + 1;
+                }
+            }
+            self.state.source_start = Some(start);
+            self.state.source_line = (line, line_start);
+        }
+    }
+
+    /// `static` and raw `value` nodes replace the value that was built
+    /// for them, which is on top of the stack.
+    fn replace_value(
+        &mut self,
+        node_id: Option<NodeId>,
+        typ: ValueType,
+        source: Option<PRange>,
+    ) -> Result<(), BailType> {
+        let replacement = match node_id {
+            Some(node_id) => match self.state.path_tracker.walker.spec.get(node_id).variant {
+                NodeVariant::Static { ref value } => Some(value.load(self.env)),
+                NodeVariant::Value { raw: true } => None,
+                _ => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+
+        let term = match replacement {
+            Some(term) => term,
+            None => {
+                let range = match source {
+                    Some(range) => range,
+                    None => self.source_range(typ),
+                };
+                match self.input.range_to_term(self.env, range) {
+                    Some(term) => term,
+                    None => return self.fail_internal("failed to allocate raw value"),
+                }
+            }
+        };
+        self.out_stack.pop();
+        self.out_stack.push(term);
+        Ok(())
+    }
+
+    /// Where a value that just ended is in the input, found from the
+    /// bytes around it.
+    fn source_range(&mut self, typ: ValueType) -> PRange {
+        let position = self.state.position;
+        let start = match typ {
+            ValueType::Object | ValueType::Array => self.state.source_start.take().unwrap(),
+            ValueType::String => {
+                raw_source::string_start(|pos| self.input_byte(pos), self.state.string_start)
+            }
+            _ => raw_source::scalar_start(|pos| self.input_byte(pos), position),
+        };
+        let end = raw_source::value_end(|pos| self.input_byte(pos), start);
+        PRange {
+            start: start,
+            end: end,
+        }
+    }
+
+    /// Decodes the object on top of the stack again with the node its
+    /// tag maps to, if it is on a `tagged_map` node.
+    fn finish_tagged(&mut self, node_id: NodeId) -> Result<(), BailType> {
+        let spec = self.state.path_tracker.walker.spec.clone();
+        let (key_name, mappings, default, streaming) = match spec.get(node_id).variant {
+            NodeVariant::TaggedMap {
+                ref key_name,
+                ref mappings,
+                ref default,
+                ref streaming,
+            } => (key_name, mappings, default, streaming),
+            _ => return Ok(()),
+        };
+        let start = self.state.source_start.take().unwrap();
+
+        let object = *self.out_stack.last().unwrap();
+        let tag = match get(object, key_name).and_then(|tag| tag.decode::<Binary>().ok()) {
+            Some(tag) => tag.as_slice().to_vec(),
+            None => return self.fail(ErrorReason::MissingTag(key_name.clone())),
+        };
+        let variant = match (mappings.get(&tag), default) {
+            (Some(&child), _) => child,
+            (None, &TagDefault::Node(child)) => child,
+            (None, &TagDefault::Ignore) => return Ok(()),
+            (None, &TagDefault::Error) => return self.fail(ErrorReason::UnknownTag(tag)),
+        };
+        if streaming.contains(&variant) {
+            let error = SpecError::Unsupported("stream inside tagged_map");
+            return self.fail(ErrorReason::Spec(error));
+        }
+
+        let end = raw_source::value_end(|pos| self.input_byte(pos), start);
+        let binaries = match self.input.slice(PRange {
+            start: start,
+            end: end,
+        }) {
+            Some(binaries) => binaries,
+            None => return self.fail_internal("tagged object is no longer held"),
+        };
+
+        let path_tracker = PathTracker {
+            path: self.state.path_tracker.path.clone(),
+            walker: SpecWalker::new_at(spec.clone(), variant),
+        };
+        let result = parse_node(
+            self.env,
+            &binaries,
+            self.state.source_line,
+            path_tracker,
+            &self.state.options,
+            self.state.depth,
+        );
+        // The object is read twice, which counts against the time slice.
+        self.next_reschedule = self.next_reschedule.saturating_sub(end - start);
+        match result {
+            Ok((term, violations)) => {
+                self.state.violations.extend(violations);
+                self.out_stack.pop();
+                self.out_stack.push(term);
+                Ok(())
+            }
+            Err(error) => {
+                if self.error.is_none() {
+                    self.error = Some(error);
+                }
+                Err(BailType::Error)
+            }
+        }
+    }
+
+    fn enter_result(&mut self, result: Result<(), SpecError>) {
+        if let Err(error) = result {
+            let _ = self.fail(ErrorReason::Spec(error));
+        }
+    }
+
+    fn do_stream(&mut self, node_id_opt: Option<NodeId>) -> Result<(), BailType> {
+        let streamed = match node_id_opt {
+            Some(node_id) => {
+                self.state
+                    .path_tracker
+                    .walker
+                    .spec
+                    .get(node_id)
+                    .options
+                    .stream
+            }
+            None => false,
+        };
+        let yields = match self.yields {
+            Some(ref mut yields) if streamed => yields,
+            _ => return Ok(()),
+        };
+        let path = self.state.path_tracker.path.encode(self.env);
+        let term = self.out_stack.pop().unwrap();
+        yields.push((::atoms::yield_(), (path, term)).encode(self.env));
+        self.collect_streamed();
+        Ok(())
+    }
+
+    /// Leaves what the container of a streamed value keeps of it on the
+    /// stack. The path has moved past the value, so the walker is at the
+    /// container.
+    fn collect_streamed(&mut self) {
+        let collect = match self.state.path_tracker.walker.container() {
+            Some(node_id) => {
+                self.state
+                    .path_tracker
+                    .walker
+                    .spec
+                    .get(node_id)
+                    .options
+                    .stream_collect
+            }
+            None => StreamCollect::Placeholder,
+        };
+        match collect {
+            StreamCollect::Placeholder => {
+                self.out_stack.push(::atoms::streamed().encode(self.env));
+                return;
+            }
+            StreamCollect::Drop | StreamCollect::Count => {
+                self.state.streamed.last_mut().unwrap().count += 1;
+            }
+        }
+        self.out_stack.push(::atoms::nil().encode(self.env));
+        self.state.value_dropped = true;
+    }
+
+    fn counts_streamed(&self, node_id: Option<NodeId>) -> bool {
+        match node_id {
+            Some(node_id) if self.yields.is_some() => {
+                let node = self.state.path_tracker.walker.spec.get(node_id);
+                node.options.stream_collect == StreamCollect::Count
+            }
+            _ => false,
+        }
+    }
+
+    /// Called once a container that is built has been entered.
+    fn enter_count(&mut self) {
+        if self.yields.is_some() && self.state.path_tracker.walker.container().is_some() {
+            self.state.streamed.push(Streamed::default());
+        }
+    }
+
+    /// How many streamed children the innermost container left out,
+    /// while it is on the spec.
+    fn streamed_children(&self) -> usize {
+        match self.state.streamed.last() {
+            Some(streamed) if self.yields.is_some() => streamed.count,
+            _ => 0,
+        }
+    }
+
+    /// Replaces a finished container with the number of its streamed
+    /// children, if its node counts them.
+    fn finish_count(&mut self, node_id: Option<NodeId>) {
+        if self.yields.is_none() || node_id.is_none() {
+            return;
+        }
+        let streamed = self.state.streamed.pop().unwrap();
+        if self.counts_streamed(node_id) {
+            self.out_stack.pop();
+            self.out_stack
+                .push((streamed.count as u64).encode(self.env));
+        }
+    }
+}
+
+impl<'a, 'b> Sink for StreamingSS<'a, 'b> {
+    fn push_map(&mut self, pos: Position) {
+        let result = self.state.path_tracker.enter_map(pos);
+        self.enter_result(result);
+        self.enter_container();
+
+        if !self.skip_container() {
+            let objects = self.nearest_options().objects;
+            self.out_stack.push(new_object(self.env, objects));
+            self.state.objects.push(OpenObject::default());
+            self.enter_source();
+            self.enter_count();
+        }
+        self.state.first_needed = self.state.position;
+    }
+    fn push_array(&mut self, pos: Position) {
+        let result = self.state.path_tracker.enter_array(pos);
+        self.enter_result(result);
+        self.enter_container();
+
+        if !self.skip_container() {
+            let arr: Vec<Term> = Vec::new();
+            self.out_stack.push(arr.encode(self.env));
+            self.enter_source();
+            self.enter_count();
+        }
+        self.state.first_needed = self.state.position;
+    }
+    fn push_number(&mut self, pos: Position, num: NumberData) -> Result<(), Self::Bail> {
+        let result = self.state.options.limits.number_digits(number_digits(&num));
+        self.check_limit(result)?;
+
+        let curr_node = self.visit_terminal(pos, ValueType::Number)?;
+        if self.skip_terminal(&curr_node) {
+            return Ok(());
+        }
+
+        let violations = match self.terminal_constraints(&curr_node) {
+            Some(constraints) if constraints.checks_numbers() => {
+                let number = number_to_f64(&num, |r, b| self.input.push_range(r, b));
+                constraints.check_number(number)
+            }
+            _ => Vec::new(),
+        };
+        self.add_violations(violations);
+
+        let source = raw_source::number_range(&num);
+        let floats = self.terminal_options(&curr_node).floats;
+        let term = number_data_to_term(self.env, num, floats, |r, b| self.input.push_range(r, b));
+        self.push_terminal(&curr_node, ValueType::Number, term, Some(source))
+    }
+    fn push_bool(&mut self, pos: Position, val: bool) -> Result<(), Self::Bail> {
+        let curr_node = self.visit_terminal(pos, ValueType::Boolean)?;
+        if self.skip_terminal(&curr_node) {
+            return Ok(());
+        }
+        let term = val.encode(self.env);
+        self.push_terminal(&curr_node, ValueType::Boolean, term, None)
+    }
+    fn push_null(&mut self, pos: Position) -> Result<(), Self::Bail> {
+        let curr_node = self.visit_terminal(pos, ValueType::Null)?;
+        if self.skip_terminal(&curr_node) {
+            return Ok(());
+        }
+        let term = ::atoms::nil().encode(self.env);
+        self.push_terminal(&curr_node, ValueType::Null, term, None)
+    }
+
+    fn start_string(&mut self, pos: StringPosition) {
+        self.state.string_start = self.state.position;
+        self.state.current_string = match pos {
+            StringPosition::MapKey => BuildString::new_owned(),
+            _ => BuildString::new(),
+        };
+    }
+    fn append_string_range(&mut self, range: PRange) {
+        let input = &self.input;
+        self.state
+            .current_string
+            .append_range(range, |r, b| input.push_range(r, b));
+        self.check_string_len();
+    }
+    fn append_string_single(&mut self, character: u8) {
+        let input = &self.input;
+        self.state
+            .current_string
+            .append_single(character, |r, b| input.push_range(r, b));
+        self.check_string_len();
+    }
+    fn append_string_codepoint(&mut self, codepoint: char) {
+        let input = &self.input;
+        self.state
+            .current_string
+            .append_codepoint(codepoint, |r, b| input.push_range(r, b));
+        self.check_string_len();
+    }
+    fn finalize_string(&mut self, pos: StringPosition) -> Result<(), Self::Bail> {
+        let string = ::std::mem::replace(&mut self.state.current_string, BuildString::None);
+        match pos {
+            StringPosition::MapKey => {
+                let key = string.owned_to_vec();
+
+                let curr_node_id = self.state.path_tracker.enter_key(key.clone());
+                if self.state.skip_from.is_none() {
+                    self.push_key(curr_node_id, &key)?;
+                }
+            }
+            _ => {
+                let curr_node = self.visit_terminal(pos.to_position(), ValueType::String)?;
+                if self.skip_terminal(&curr_node) {
+                    return Ok(());
+                }
+
+                let spec = self.state.path_tracker.walker.spec.clone();
+                // Strings that are looked at are copied out once.
+                let checked = curr_node
+                    .current
+                    .map(|node_id| spec.get(node_id))
+                    .filter(|node| node.is_enum() || node.options.constraints.checks_strings());
+
+                let result = match checked {
+                    Some(node) => {
+                        let bytes = string.to_vec(&self.input);
+                        self.add_violations(node.options.constraints.check_string(&bytes));
+                        if node.is_enum() {
+                            finish_enum(self.env, node, Some(&bytes))
+                        } else {
+                            binary_from_slice(self.env, &bytes).ok_or_else(|| {
+                                ErrorReason::Internal("failed to allocate string".to_string())
+                            })
+                        }
+                    }
+                    None => string.to_term(&mut self.input, self.env).ok_or_else(|| {
+                        ErrorReason::Internal("failed to allocate string".to_string())
+                    }),
+                };
+
+                return match result {
+                    Ok(term) => self.push_terminal(&curr_node, ValueType::String, term, None),
+                    Err(reason) => self.fail(reason),
+                };
+            }
+        }
+        self.state.first_needed = self.state.position;
+        Ok(())
+    }
+
+    fn finalize_map(&mut self, _pos: Position) -> Result<(), Self::Bail> {
+        self.state.depth -= 1;
+        if self.state.skip_from.is_some() {
+            self.state.path_tracker.exit_map();
+            self.exit_skipped();
+            return Ok(());
+        }
+        let open = self.state.objects.pop().unwrap();
+        let term = self.out_stack.pop().unwrap();
+        let completed = match self.state.path_tracker.walker.container() {
+            Some(node_id) => {
+                let node = self.state.path_tracker.walker.spec.get(node_id);
+                complete_object(self.env, node, term, &open.present)
+            }
+            None => Ok(term),
+        };
+        let term = match completed {
+            Ok(object) => object,
+            Err(reason) => {
+                self.out_stack.push(term);
+                return self.fail(reason);
+            }
+        };
+
+        let objects = self.nearest_options().objects;
+        match finish(term, objects) {
+            Some(object) => self.out_stack.push(object),
+            None => {
+                self.out_stack.push(term);
+                return self.fail_internal("failed to reverse object");
+            }
+        }
+
+        if let Some(node_id) = self.state.path_tracker.walker.container() {
+            self.finish_tagged(node_id)?;
+        }
+        let node_id = self.state.path_tracker.walker.container();
+        self.check_type(node_id, ValueType::Object);
+        self.finish_non_string(node_id, ValueType::Object)?;
+
+        self.state.first_needed = self.state.position;
+
+        let curr_node = self.state.path_tracker.exit_map();
+
+        let struct_atom = curr_node.current.and_then(|node_id| {
+            let curr_node = self.state.path_tracker.walker.spec.get(node_id);
+            match curr_node.options.struct_atom {
+                Some(ref atom) => Some(atom.clone()),
+                None => None,
+            }
+        });
+
+        if let Some(atom) = struct_atom {
+            let term = self.out_stack.pop().unwrap();
+            match term.map_put(
+                ::atoms::__struct__().encode(self.env),
+                atom.encode(self.env),
+            ) {
+                Ok(term) => self.out_stack.push(term),
+                Err(_) => {
+                    self.out_stack.push(term);
+                    return self.fail_internal("failed to insert into map");
+                }
+            }
+        }
+
+        self.finish_count(curr_node.current);
+        self.replace_value(curr_node.current, ValueType::Object, None)?;
+        self.do_stream(curr_node.current)?;
+        self.end_value();
+
+        Ok(())
+    }
+    fn finalize_array(&mut self, _pos: Position) -> Result<(), Self::Bail> {
+        self.state.depth -= 1;
+        if self.state.skip_from.is_some() {
+            self.state.path_tracker.exit_array();
+            self.exit_skipped();
+            return Ok(());
+        }
+        let term = self.out_stack.pop().unwrap();
+        let list = match term.list_reverse() {
+            Ok(reversed) => reversed,
+            Err(_) => {
+                self.out_stack.push(term);
+                return self.fail_internal("failed to reverse array");
+            }
+        };
+
+        let shaped = match self.state.path_tracker.walker.container() {
+            Some(node_id) => {
+                let node = self.state.path_tracker.walker.spec.get(node_id);
+                let streamed = self.streamed_children();
+                finish_array(self.env, node, list, streamed)
+            }
+            None => Ok(list),
+        };
+        match shaped {
+            Ok(term) => self.out_stack.push(term),
+            Err(reason) => {
+                self.out_stack.push(list);
+                let path = self.state.path_tracker.container_pointer();
+                return self.fail_at(reason, path);
+            }
+        }
+        let node_id = self.state.path_tracker.walker.container();
+        self.check_type(node_id, ValueType::Array);
+        self.finish_non_string(node_id, ValueType::Array)?;
+
+        self.state.first_needed = self.state.position;
+
+        let curr_node = self.state.path_tracker.exit_array();
+        self.finish_count(curr_node.current);
+        self.replace_value(curr_node.current, ValueType::Array, None)?;
+        self.do_stream(curr_node.current)?;
+        self.end_value();
+
+        Ok(())
+    }
+    fn pop_into_map(&mut self) {
+        if self.state.skip_from.is_some() {
+            return;
+        }
+        let key_index = self.out_stack.len() - 2;
+        let value = self.out_stack.pop().unwrap();
+        let key = self.out_stack.pop().unwrap();
+        let map = self.out_stack.pop().unwrap();
+
+        if self.state.ignored_keys.last() == Some(&key_index) {
+            self.state.ignored_keys.pop();
+            self.out_stack.push(map);
+            return;
+        }
+        if self.state.value_dropped {
+            self.state.value_dropped = false;
+            self.out_stack.push(map);
+            return;
+        }
+
+        let duplicate_keys = self.nearest_options().duplicate_keys;
+        let open = self.state.objects.last_mut().unwrap();
+        match insert(self.env, open, map, key, value, duplicate_keys) {
+            Ok(map) => {
+                self.out_stack.push(map);
+                let keys = self.state.objects.last().unwrap().keys;
+                let result = self.state.options.limits.keys_per_object(keys);
+                let _ = self.check_limit(result);
+            }
+            Err(InsertError::Duplicate) => {
+                self.out_stack.push(map);
+                let _ = self.fail_duplicate_key(key);
+            }
+            Err(InsertError::Failed) => {
+                self.out_stack.push(map);
+                let _ = self.fail_internal("failed to insert into map");
+            }
+        }
+    }
+    fn pop_into_array(&mut self) {
+        if self.state.skip_from.is_some() {
+            return;
+        }
+        let value = self.out_stack.pop().unwrap();
+        if self.state.value_dropped {
+            self.state.value_dropped = false;
+            return;
+        }
+        let array = self.out_stack.pop().unwrap();
+        self.out_stack.push(array.list_prepend(value));
+    }
+}
@@ -0,0 +1,124 @@
+//! Shapes parsed values into the terms asked for by spec nodes, for the
+//! spec sink.
+
+use rustler::types::tuple::make_tuple;
+use rustler::{Encoder, Env, Term};
+
+use std::collections::HashSet;
+
+use errors::ErrorReason;
+use objects::{insert, OpenObject};
+use options::DuplicateKeys;
+use strings::binary_from_slice;
+use tree_spec::{Node, NodeOptions, NodeVariant};
+
+/// The term for a key of an object, from the options of its node if it
+/// is on the spec. `None` for keys that are left out.
+pub fn key_term<'a>(
+    env: Env<'a>,
+    options: Option<&NodeOptions>,
+    key: &[u8],
+) -> Result<Option<Term<'a>>, ErrorReason> {
+    if let Some(options) = options {
+        let atom = match options.atom_mappings {
+            Some(ref mappings) => mappings.get(key),
+            None => None,
+        };
+        match atom {
+            Some(atom) => return Ok(Some(atom.encode(env))),
+            None if options.reject_non_atoms => return Err(ErrorReason::UnknownKey(key.to_vec())),
+            None if options.ignore_non_atoms => return Ok(None),
+            None => (),
+        }
+    }
+    match binary_from_slice(env, key) {
+        Some(term) => Ok(Some(term)),
+        None => Err(ErrorReason::Internal("failed to allocate key".to_string())),
+    }
+}
+
+/// Called with the finished list of an array that is on the spec.
+/// `streamed` elements were left out of it, they count towards the arity
+/// of a tuple.
+pub fn finish_array<'a>(
+    env: Env<'a>,
+    node: &Node,
+    list: Term<'a>,
+    streamed: usize,
+) -> Result<Term<'a>, ErrorReason> {
+    match node.variant {
+        NodeVariant::Tuple { ref children } => {
+            let elements: Vec<Term<'a>> = list
+                .decode()
+                .map_err(|_| ErrorReason::Internal("failed to read array".to_string()))?;
+            if elements.len() + streamed != children.len() {
+                return Err(ErrorReason::WrongArity {
+                    expected: children.len(),
+                    found: elements.len() + streamed,
+                });
+            }
+            if node.options.as_tuple {
+                Ok(make_tuple(env, &elements))
+            } else {
+                Ok(list)
+            }
+        }
+        _ => Ok(list),
+    }
+}
+
+/// Called with the contents of a string on an `enum` node, or `None`
+/// for a value that is not a string.
+pub fn finish_enum<'a>(
+    env: Env<'a>,
+    node: &Node,
+    string: Option<&[u8]>,
+) -> Result<Term<'a>, ErrorReason> {
+    match node.variant {
+        NodeVariant::Enum {
+            ref values,
+            ref default,
+        } => match (string.and_then(|string| values.get(string)), default) {
+            (Some(atom), _) => Ok(atom.encode(env)),
+            (None, &Some(ref default)) => Ok(default.load(env)),
+            (None, &None) => Err(ErrorReason::InvalidEnumValue(string.map(|s| s.to_vec()))),
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Called with an object on the spec before it is finished. Checks its
+/// required keys, then fills in defaults for keys it does not have.
+/// `present` are the keys the object has in the input, including those
+/// that were ignored or streamed and are left out of it.
+pub fn complete_object<'a>(
+    env: Env<'a>,
+    node: &Node,
+    object: Term<'a>,
+    present: &HashSet<Vec<u8>>,
+) -> Result<Term<'a>, ErrorReason> {
+    let missing: Vec<Vec<u8>> = node
+        .options
+        .required
+        .iter()
+        .filter(|key| !present.contains(*key))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(ErrorReason::MissingKeys(missing));
+    }
+
+    let mut object = object;
+    // Defaults replace nothing, so their keys are not tracked.
+    let mut open = OpenObject::default();
+    for default in node.options.defaults.iter() {
+        if present.contains(&default.key_bytes) {
+            continue;
+        }
+        let key = default.key.load(env);
+        let value = default.value.load(env);
+        object = insert(env, &mut open, object, key, value, DuplicateKeys::Last)
+            .map_err(|_| ErrorReason::Internal("failed to insert default".to_string()))?;
+    }
+    Ok(object)
+}
@@ -1,13 +1,16 @@
-use iterative_json_parser::{ParseError, Parser, Pos, Unexpected};
+use iterative_json_parser::{ParseError, Parser};
 
 use rustler::resource::ResourceArc;
 use rustler::types::binary::Binary;
 use rustler::types::list::ListIterator;
 use rustler::{Encoder, Env, NifResult, Term};
 
-use strings::BuildString;
+use errors::{DecodeError, ErrorReason, Location};
+use input_provider::streaming::StreamingInputResult;
+use input_provider::InputProvider;
+use options::{read_parse_opts, Documents, Limit, RECORD_SEPARATOR};
 
-use tree_spec::spec_from_term;
+use tree_spec::read_spec;
 use tree_spec::SpecWalker;
 
 use input_provider::streaming::StreamingInputProvider;
@@ -18,23 +21,34 @@ use std::ops::DerefMut;
 use std::ops::Range;
 use std::sync::Mutex;
 
-mod source_sink;
-use self::source_sink::{SSState, StreamingSS};
+use spec_sink::{BailType, DocumentState, SSState, StreamingSS};
 
-#[derive(Copy, Clone)]
-pub enum BailType {
-    Reschedule,
-    AwaitInput,
+/// Locates an error using the line information tracked by the sink and
+/// whatever input is still held.
+fn locate(input: &StreamingInputProvider, state: &SSState, error: &DecodeError) -> Location {
+    let offset = error.position;
+    let (line, line_start) = error.line.unwrap_or((state.line, state.line_start));
+    let mut snippet = Vec::new();
+    for pos in Location::snippet_range(offset) {
+        if let StreamingInputResult::Ok(byte) = input.byte(pos) {
+            snippet.push(byte);
+        }
+    }
+    Location {
+        line: line,
+        column: offset.saturating_sub(line_start) + 1,
+        snippet: snippet,
+    }
 }
 
-fn format_unexpected(env: Env, pos: Pos, reason: Unexpected) -> Term {
-    let position = pos.0 as u64;
-    let explaination = reason.explain().encode(env);
-    (
-        ::atoms::error(),
-        (::atoms::unexpected(), position, explaination),
-    )
-        .encode(env)
+fn format_error<'a>(
+    env: Env<'a>,
+    input: &StreamingInputProvider,
+    state: &SSState,
+    error: DecodeError,
+) -> Term<'a> {
+    let location = locate(input, state, &error);
+    (::atoms::error(), error.to_term(env, &location)).encode(env)
 }
 
 pub struct StreamingIterState {
@@ -67,19 +81,20 @@ fn write_binaries<'a>(
     res.encode(env)
 }
 
-pub fn parse_init<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Term<'a>> {
-    let spec = spec_from_term(term)?;
-
-    let ss_state = SSState {
-        path_tracker: PathTracker {
-            path: Vec::new(),
-            walker: SpecWalker::new(spec),
-        },
+pub fn parse_init<'a>(env: Env<'a>, term: Term<'a>, opts_term: Term<'a>) -> NifResult<Term<'a>> {
+    let options = read_parse_opts(opts_term)?;
+    let spec = read_spec(term, &options)?;
+    let document = match options.documents {
+        Documents::Single => DocumentState::Parsing,
+        _ => DocumentState::Between,
+    };
 
-        position: 0,
-        first_needed: 0,
-        current_string: BuildString::None,
+    let path_tracker = PathTracker {
+        path: Vec::new(),
+        walker: SpecWalker::new(spec),
     };
+    let mut ss_state = SSState::new(path_tracker, options, 0);
+    ss_state.document = document;
 
     let iter_state = StreamingIterState {
         parser: Parser::new(),
@@ -92,52 +107,235 @@ pub fn parse_init<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Term<'a>> {
     Ok((::atoms::ok(), state).encode(env))
 }
 
-pub fn parse_iter<'a>(env: Env<'a>, binaries: Term<'a>, parser: Term<'a>) -> NifResult<Term<'a>> {
-    let binaries_ranges: Vec<(Range<usize>, Binary)> = read_binaries(binaries)?;
-    let (stack, resource): (Vec<Term<'a>>, ResourceArc<StreamingIterStateWrapper>) =
-        parser.decode()?;
+/// Why the input between two documents could not be moved past.
+enum Gap {
+    AwaitInput,
+    Eof,
+    Invalid(ErrorReason),
+}
 
-    let (res, out_stack, mut yields, first_needed) = {
-        let mut resource_inner_guard = resource.0.lock().unwrap();
-        let mut iter_state = resource_inner_guard.deref_mut();
-
-        let mut ss = StreamingSS {
-            env: env,
-            input: StreamingInputProvider {
-                binaries: &binaries_ranges,
-            },
-            next_reschedule: iter_state.ss_state.position + 40_000,
-            out_stack: stack,
-            state: &mut iter_state.ss_state,
-            yields: Vec::new(),
+fn is_whitespace(byte: u8) -> bool {
+    match byte {
+        b' ' | b'\t' | b'\r' | b'\n' => true,
+        _ => false,
+    }
+}
+
+/// Moves over the input after a document and before the next one. Stops
+/// once a document is known to be complete, so that it is only emitted
+/// then, and at the start of the next one.
+fn skip_between(input: StreamingInputProvider, state: &mut SSState) -> Result<(), Gap> {
+    let documents = state.options.documents;
+    loop {
+        let byte = match input.byte(state.position) {
+            StreamingInputResult::Ok(byte) => Some(byte),
+            StreamingInputResult::AwaitInput => return Err(Gap::AwaitInput),
+            StreamingInputResult::Eof => None,
         };
 
-        let res = iter_state.parser.run(&mut ss);
-        (res, ss.out_stack, ss.yields, ss.state.first_needed)
-    };
+        match (state.document, byte) {
+            (DocumentState::Ended, _) if documents == Documents::Concatenated => return Ok(()),
+            (DocumentState::Ended, None) if documents == Documents::Lines => return Ok(()),
+            (DocumentState::Ended, Some(b'\n')) => return Ok(()),
+            // A text of a sequence that is not followed by a newline may
+            // have been cut off.
+            (DocumentState::Ended, None) => return Err(Gap::Invalid(ErrorReason::Truncated)),
+            (DocumentState::Ended, Some(RECORD_SEPARATOR)) if documents == Documents::Sequence => {
+                return Err(Gap::Invalid(ErrorReason::Truncated))
+            }
+            (DocumentState::Ended, Some(byte)) if !is_whitespace(byte) => {
+                return Err(Gap::Invalid(ErrorReason::ExpectedNewline))
+            }
 
-    let binaries_out = write_binaries(env, &binaries_ranges, first_needed);
+            (_, None) => return Err(Gap::Eof),
 
-    match res {
-        Ok(()) => {
-            yields.push(::atoms::finished().encode(env));
-            let state = (out_stack, resource).encode(env);
-            Ok((::atoms::finished(), yields, binaries_out, state).encode(env))
+            (DocumentState::Discarding, Some(byte)) if documents.ends_document(byte) => {
+                state.document = DocumentState::Between;
+                if byte == RECORD_SEPARATOR {
+                    continue;
+                }
+            }
+            (DocumentState::Discarding, _) => (),
+
+            (_, Some(RECORD_SEPARATOR)) if documents == Documents::Sequence => {
+                state.document = DocumentState::Separated;
+            }
+            (_, Some(byte)) if is_whitespace(byte) => (),
+            (DocumentState::Between, Some(_)) if documents == Documents::Sequence => {
+                return Err(Gap::Invalid(ErrorReason::ExpectedSeparator))
+            }
+            _ => return Ok(()),
+        }
+
+        if byte == Some(b'\n') {
+            state.line += 1;
+            state.line_start = state.position + 1;
         }
-        Err(ParseError::SourceBail(BailType::Reschedule)) => {
-            let state = (out_stack, resource).encode(env);
-            Ok((::atoms::iter(), yields, binaries_out, state).encode(env))
+        state.position += 1;
+        state.first_needed = state.position;
+    }
+}
+
+/// Moves on to the next line or record after a document failed for
+/// `reason`, if the stream goes on after that. Going over
+/// `:max_total_bytes` ends the stream, as the limit is on all of it.
+fn skip_failed(state: &mut SSState, reason: &ErrorReason) -> bool {
+    match (state.options.documents, reason) {
+        (_, &ErrorReason::LimitExceeded(Limit::TotalBytes)) => false,
+        (Documents::Lines, _) | (Documents::Sequence, _) if state.options.skip_invalid => {
+            state.document = DocumentState::Discarding;
+            true
         }
-        Err(ParseError::SourceBail(BailType::AwaitInput)) => {
-            let state = (out_stack, resource).encode(env);
-            Ok((::atoms::await_input(), yields, binaries_out, state).encode(env))
+        _ => false,
+    }
+}
+
+/// Whether a document that failed at `pos` was cut off by the end of its
+/// input.
+fn is_truncated(input: StreamingInputProvider, documents: Documents, pos: usize) -> bool {
+    match input.byte(pos) {
+        StreamingInputResult::Ok(byte) => documents.ends_document(byte),
+        StreamingInputResult::AwaitInput => false,
+        StreamingInputResult::Eof => true,
+    }
+}
+
+pub fn parse_iter<'a>(env: Env<'a>, binaries: Term<'a>, parser: Term<'a>) -> NifResult<Term<'a>> {
+    let binaries_ranges: Vec<(Range<usize>, Binary)> = read_binaries(binaries)?;
+    let (mut stack, resource): (Vec<Term<'a>>, ResourceArc<StreamingIterStateWrapper>) =
+        parser.decode()?;
+
+    let mut resource_inner_guard = resource.0.lock().unwrap();
+    let iter_state = resource_inner_guard.deref_mut();
+
+    let input = StreamingInputProvider {
+        binaries: &binaries_ranges,
+        end: iter_state.ss_state.input_end,
+    };
+    let next_reschedule = iter_state.ss_state.position + 40_000;
+    let mut yields: Vec<Term<'a>> = Vec::new();
+
+    let status = loop {
+        if iter_state.ss_state.document != DocumentState::Parsing {
+            let state = &mut iter_state.ss_state;
+            match skip_between(input, state) {
+                Ok(()) => (),
+                Err(Gap::AwaitInput) => break ::atoms::await_input(),
+                Err(Gap::Eof) => {
+                    yields.push(::atoms::finished().encode(env));
+                    break ::atoms::finished();
+                }
+                Err(Gap::Invalid(reason)) => {
+                    let skip = skip_failed(state, &reason);
+                    let error = DecodeError::new(reason, state.position, String::new());
+                    yields.push(format_error(env, &input, state, error));
+                    if skip {
+                        continue;
+                    }
+                    break ::atoms::finished();
+                }
+            }
+            if state.document == DocumentState::Ended {
+                let value = stack.pop().unwrap();
+                let document = match state.options.documents {
+                    Documents::Lines => (::atoms::line(), state.document_line as u64, value),
+                    _ => (::atoms::document(), state.document_count as u64 - 1, value),
+                };
+                yields.push(document.encode(env));
+                state.document = DocumentState::Between;
+                continue;
+            }
+            if state.position >= next_reschedule {
+                break ::atoms::iter();
+            }
+            iter_state.parser = Parser::new();
+            state.reset_document();
+            stack.clear();
         }
-        Err(ParseError::Unexpected(pos, reason)) => {
-            let error = format_unexpected(env, pos, reason);
-            yields.push(error);
-            let state = (out_stack, resource).encode(env);
-            Ok((::atoms::finished(), yields, binaries_out, state).encode(env))
+
+        let (res, sink_error) = {
+            let mut ss = StreamingSS {
+                env: env,
+                input: input,
+                next_reschedule: next_reschedule,
+                out_stack: stack,
+                state: &mut iter_state.ss_state,
+                yields: Some(Vec::new()),
+                error: None,
+            };
+
+            let res = iter_state.parser.run(&mut ss);
+            stack = ss.out_stack;
+            yields.extend(ss.yields.unwrap());
+            (res, ss.error)
+        };
+
+        let state = &mut iter_state.ss_state;
+        let error = match res {
+            Ok(()) | Err(ParseError::SourceBail(BailType::DocumentEnd)) => {
+                if state.violations.is_empty() {
+                    if state.options.documents == Documents::Single {
+                        yields.push(::atoms::finished().encode(env));
+                        break ::atoms::finished();
+                    }
+                    state.document = DocumentState::Ended;
+                    continue;
+                }
+                let violations = ::std::mem::replace(&mut state.violations, Vec::new());
+                DecodeError::new(
+                    ErrorReason::ConstraintViolations(violations),
+                    state.position,
+                    String::new(),
+                )
+            }
+            Err(ParseError::SourceBail(BailType::Reschedule)) => break ::atoms::iter(),
+            Err(ParseError::SourceBail(BailType::AwaitInput)) => break ::atoms::await_input(),
+            Err(ParseError::SourceBail(BailType::Error)) => sink_error.unwrap_or_else(|| {
+                DecodeError::internal("sink failed", state.position, String::new())
+            }),
+            Err(ParseError::Unexpected(pos, reason)) => {
+                let reason = if is_truncated(input, state.options.documents, pos.0) {
+                    ErrorReason::Truncated
+                } else {
+                    ErrorReason::Unexpected(reason)
+                };
+                DecodeError::new(reason, pos.0, state.path_tracker.json_pointer())
+            }
+            Err(err) => sink_error.unwrap_or_else(|| {
+                DecodeError::internal(
+                    &format!("{:?}", err),
+                    state.position,
+                    state.path_tracker.json_pointer(),
+                )
+            }),
+        };
+        let skip = skip_failed(state, &error.reason);
+        yields.push(format_error(env, &input, state, error));
+        if !skip {
+            break ::atoms::finished();
         }
-        Err(_) => panic!("TODO: Add proper error"),
+    };
+
+    let binaries_out = write_binaries(env, &binaries_ranges, iter_state.ss_state.needed_from());
+    let state = (stack, &resource).encode(env);
+    Ok((status, yields, binaries_out, state).encode(env))
+}
+
+/// Marks the input as ended at the end of `binaries`, then parses what
+/// is left of it.
+pub fn parse_end<'a>(env: Env<'a>, binaries: Term<'a>, parser: Term<'a>) -> NifResult<Term<'a>> {
+    {
+        let binaries_ranges: Vec<(Range<usize>, Binary)> = read_binaries(binaries)?;
+        let (_, resource): (Term<'a>, ResourceArc<StreamingIterStateWrapper>) = parser.decode()?;
+        let mut resource_inner_guard = resource.0.lock().unwrap();
+        let iter_state = resource_inner_guard.deref_mut();
+
+        let end = binaries_ranges
+            .iter()
+            .map(|&(ref range, _)| range.end)
+            .max();
+        let end = end.unwrap_or(iter_state.ss_state.position);
+        iter_state.ss_state.input_end = Some(end);
     }
+    parse_iter(env, binaries, parser)
 }
@@ -1,11 +1,18 @@
 use iterative_json_parser::Range;
-use std::io::Write;
 
 use rustler::types::binary::OwnedBinary;
 use rustler::{Encoder, Env, Term};
 
 use input_provider::InputProvider;
 
+/// Copies bytes into a new binary term. Returns `None` if the binary
+/// could not be allocated.
+pub fn binary_from_slice<'a>(env: Env<'a>, bytes: &[u8]) -> Option<Term<'a>> {
+    let mut bin = OwnedBinary::new(bytes.len())?;
+    bin.as_mut_slice().copy_from_slice(bytes);
+    Some(bin.release(env).encode(env))
+}
+
 pub enum BuildString {
     None,
     Range(Range),
@@ -86,6 +93,15 @@ impl BuildString {
         }
     }
 
+    /// Length of the string so far, in bytes.
+    pub fn len(&self) -> usize {
+        match *self {
+            BuildString::None => 0,
+            BuildString::Range(range) => range.end - range.start,
+            BuildString::Owned(ref buf) => buf.len(),
+        }
+    }
+
     pub fn owned_to_vec(self) -> Vec<u8> {
         match self {
             BuildString::Owned(vec) => vec,
@@ -93,18 +109,30 @@ impl BuildString {
         }
     }
 
-    pub fn to_term<'a, T, M>(self, input: &mut T, env: Env<'a>) -> Term<'a>
+    pub fn to_vec<T, M>(self, input: &T) -> Vec<u8>
     where
         T: InputProvider<M>,
     {
         match self {
-            BuildString::None => "".encode(env),
-            BuildString::Range(range) => input.range_to_term(env, range),
-            BuildString::Owned(ref buf) => {
-                let mut bin = OwnedBinary::new(buf.len()).unwrap();
-                bin.as_mut_slice().write(buf).unwrap();
-                bin.release(env).encode(env)
+            BuildString::None => Vec::new(),
+            BuildString::Range(range) => {
+                let mut buf = Vec::new();
+                input.push_range(range, &mut buf);
+                buf
             }
+            BuildString::Owned(buf) => buf,
+        }
+    }
+
+    /// Returns `None` if the binary could not be allocated.
+    pub fn to_term<'a, T, M>(self, input: &mut T, env: Env<'a>) -> Option<Term<'a>>
+    where
+        T: InputProvider<M>,
+    {
+        match self {
+            BuildString::None => Some("".encode(env)),
+            BuildString::Range(range) => input.range_to_term(env, range),
+            BuildString::Owned(ref buf) => binary_from_slice(env, buf),
         }
     }
 }
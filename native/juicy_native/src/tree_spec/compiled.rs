@@ -0,0 +1,67 @@
+use rustler::resource::ResourceArc;
+use rustler::{Encoder, Env, NifResult, Term};
+use std::sync::Arc;
+
+use options::{DuplicateKeys, Floats, Objects, ParseOptions};
+
+use super::{spec_from_schema, spec_from_term, SchemaError, Spec, StoredTerm};
+
+mod atoms {
+    atoms! {
+        json_schema,
+    }
+}
+
+/// A spec read ahead of time, handed to Elixir as a resource so parses
+/// can skip reading the spec term.
+pub struct CompiledSpec {
+    spec: Arc<Spec>,
+    /// Kept to read the spec again for parses with other defaults.
+    term: StoredTerm,
+    defaults: (DuplicateKeys, Objects, Floats),
+}
+
+/// The parse options that spec nodes inherit.
+fn inherited_defaults(options: &ParseOptions) -> (DuplicateKeys, Objects, Floats) {
+    (options.duplicate_keys, options.objects, options.floats)
+}
+
+pub fn compile_spec(term: Term, options: &ParseOptions) -> NifResult<ResourceArc<CompiledSpec>> {
+    let spec = spec_from_term(term, options)?;
+    Ok(ResourceArc::new(CompiledSpec {
+        spec: Arc::new(spec),
+        term: StoredTerm::new(term),
+        defaults: inherited_defaults(options),
+    }))
+}
+
+/// Compiles a JSON Schema. It is kept as a `{:json_schema, schema}` node,
+/// which reads the schema in place of options, to be read again.
+pub fn compile_schema<'a>(
+    env: Env<'a>,
+    schema: Term<'a>,
+    options: &ParseOptions,
+) -> Result<ResourceArc<CompiledSpec>, SchemaError> {
+    let spec = spec_from_schema(schema, options)?;
+    Ok(ResourceArc::new(CompiledSpec {
+        spec: Arc::new(spec),
+        term: StoredTerm::new((atoms::json_schema(), schema).encode(env)),
+        defaults: inherited_defaults(options),
+    }))
+}
+
+/// Reads the spec of a parse, either a spec term or a compiled spec.
+/// A compiled spec is read again if it was compiled with other defaults
+/// for inherited options, so both give the same result.
+pub fn read_spec(term: Term, options: &ParseOptions) -> NifResult<Arc<Spec>> {
+    match term.decode::<ResourceArc<CompiledSpec>>() {
+        Ok(ref compiled) if compiled.defaults == inherited_defaults(options) => {
+            Ok(compiled.spec.clone())
+        }
+        Ok(compiled) => {
+            let spec = spec_from_term(compiled.term.load(term.get_env()), options)?;
+            Ok(Arc::new(spec))
+        }
+        Err(_) => Ok(Arc::new(spec_from_term(term, options)?)),
+    }
+}
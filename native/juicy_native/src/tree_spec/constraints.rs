@@ -0,0 +1,250 @@
+use regex::Regex;
+
+use errors::ErrorReason;
+
+use super::ValueType;
+
+/// A bound on numbers. Wrapped so options can be compared.
+#[derive(Debug, Copy, Clone)]
+pub struct Bound(pub f64);
+impl PartialEq for Bound {
+    fn eq(&self, other: &Bound) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+impl Eq for Bound {}
+
+/// A regex that strings must contain a match for.
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+impl Eq for Pattern {}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Email,
+    Uuid,
+    /// RFC 3339
+    DateTime,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Email => "email",
+            Format::Uuid => "uuid",
+            Format::DateTime => "date_time",
+        }
+    }
+
+    fn matches(self, string: &str) -> bool {
+        match self {
+            Format::Email => is_email(string),
+            Format::Uuid => is_uuid(string),
+            Format::DateTime => is_date_time(string),
+        }
+    }
+}
+
+/// Checks on terminal values. Checks for one type of value are ignored
+/// for values of another type.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Constraints {
+    /// In codepoints.
+    pub min_length: Option<usize>,
+    /// In codepoints.
+    pub max_length: Option<usize>,
+    pub pattern: Option<Pattern>,
+    pub format: Option<Format>,
+    /// The only strings allowed, from `enum` and `const` of JSON Schema.
+    pub values: Option<Vec<Vec<u8>>>,
+    pub min: Option<Bound>,
+    pub max: Option<Bound>,
+    pub integer: bool,
+    /// The only types allowed, from `type` of JSON Schema.
+    pub types: Option<Vec<ValueType>>,
+}
+
+impl Constraints {
+    pub fn checks_strings(&self) -> bool {
+        self.min_length.is_some()
+            || self.max_length.is_some()
+            || self.pattern.is_some()
+            || self.format.is_some()
+            || self.values.is_some()
+    }
+
+    pub fn checks_numbers(&self) -> bool {
+        self.min.is_some() || self.max.is_some() || self.integer
+    }
+
+    /// Checked for values of every type, containers included.
+    pub fn check_type(&self, typ: ValueType) -> Option<ErrorReason> {
+        match self.types {
+            Some(ref types) if !types.contains(&typ) => {
+                Some(ErrorReason::TypeMismatch(types.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns every check the string fails.
+    pub fn check_string(&self, string: &[u8]) -> Vec<ErrorReason> {
+        let mut violations = Vec::new();
+
+        match self.values {
+            Some(ref values) if !values.iter().any(|value| value.as_slice() == string) => {
+                violations.push(ErrorReason::InvalidEnumValue(Some(string.to_vec())))
+            }
+            _ => (),
+        }
+
+        let length = string.iter().filter(|&&byte| byte & 0xC0 != 0x80).count();
+        match self.min_length {
+            Some(min) if length < min => violations.push(ErrorReason::TooShort(min)),
+            _ => (),
+        }
+        match self.max_length {
+            Some(max) if length > max => violations.push(ErrorReason::TooLong(max)),
+            _ => (),
+        }
+
+        // Strings from the parser are valid UTF-8.
+        let string = String::from_utf8_lossy(string);
+        match self.pattern {
+            Some(Pattern(ref regex)) if !regex.is_match(&string) => {
+                violations.push(ErrorReason::PatternMismatch(regex.as_str().to_string()))
+            }
+            _ => (),
+        }
+        match self.format {
+            Some(format) if !format.matches(&string) => {
+                violations.push(ErrorReason::InvalidFormat(format.name()))
+            }
+            _ => (),
+        }
+
+        violations
+    }
+
+    /// Returns every check the number fails.
+    pub fn check_number(&self, number: f64) -> Vec<ErrorReason> {
+        let mut violations = Vec::new();
+        match self.min {
+            Some(Bound(min)) if number < min => violations.push(ErrorReason::TooSmall(min)),
+            _ => (),
+        }
+        match self.max {
+            Some(Bound(max)) if number > max => violations.push(ErrorReason::TooLarge(max)),
+            _ => (),
+        }
+        if self.integer && number.fract() != 0.0 {
+            violations.push(ErrorReason::NotInteger);
+        }
+        violations
+    }
+}
+
+/// Deliberately loose: something before the `@`, and a domain with a dot
+/// in it after. No whitespace anywhere.
+fn is_email(string: &str) -> bool {
+    if string.chars().any(char::is_whitespace) {
+        return false;
+    }
+    let mut parts = string.rsplitn(2, '@');
+    let domain = parts.next().unwrap_or("");
+    let local = match parts.next() {
+        Some(local) => local,
+        None => return false,
+    };
+    !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains('@')
+}
+
+fn is_uuid(string: &str) -> bool {
+    let groups: Vec<&str> = string.split('-').collect();
+    let lengths = [8, 4, 4, 4, 12];
+    groups.len() == lengths.len()
+        && groups.iter().zip(lengths.iter()).all(|(group, &length)| {
+            group.len() == length && group.bytes().all(|byte| (byte as char).is_digit(16))
+        })
+}
+
+/// Reads `length` digits at `pos` as a number.
+fn digits(bytes: &[u8], pos: usize, length: usize) -> Option<u32> {
+    let slice = bytes.get(pos..pos + length)?;
+    let mut number = 0;
+    for &byte in slice {
+        number = number * 10 + (byte as char).to_digit(10)?;
+    }
+    Some(number)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// `date-time` of RFC 3339, like `2024-02-29T13:05:00.25+01:00`.
+fn is_date_time(string: &str) -> bool {
+    let bytes = string.as_bytes();
+    let field = |pos, length| digits(bytes, pos, length);
+    let at =
+        |pos: usize, expected: &[u8]| bytes.get(pos).map_or(false, |byte| expected.contains(byte));
+
+    let (year, month, day, hour, minute, second) = match (
+        field(0, 4),
+        field(5, 2),
+        field(8, 2),
+        field(11, 2),
+        field(14, 2),
+        field(17, 2),
+    ) {
+        (Some(y), Some(mo), Some(d), Some(h), Some(mi), Some(s)) => (y, mo, d, h, mi, s),
+        _ => return false,
+    };
+    if !(at(4, b"-") && at(7, b"-") && at(10, b"Tt") && at(13, b":") && at(16, b":")) {
+        return false;
+    }
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+        return false;
+    }
+    // A second of 60 is a leap second.
+    if hour > 23 || minute > 59 || second > 60 {
+        return false;
+    }
+
+    let mut pos = 19;
+    if at(pos, b".") {
+        pos += 1;
+        let start = pos;
+        while at(pos, b"0123456789") {
+            pos += 1;
+        }
+        if pos == start {
+            return false;
+        }
+    }
+
+    if at(pos, b"Zz") {
+        return pos + 1 == bytes.len();
+    }
+    if !(at(pos, b"+-") && at(pos + 3, b":")) {
+        return false;
+    }
+    match (field(pos + 1, 2), field(pos + 4, 2)) {
+        (Some(hours), Some(minutes)) => hours <= 23 && minutes <= 59 && pos + 6 == bytes.len(),
+        _ => false,
+    }
+}
@@ -0,0 +1,609 @@
+//! Compiles a subset of JSON Schema (draft 2020-12) into spec nodes.
+//!
+//! The schema is given as decoded JSON, a map with binary keys. Supported
+//! are `type`, `properties`, `required`, `items`, `enum`, `const`,
+//! `minimum`, `maximum`, `minLength`, `maxLength`, `pattern`, `format`,
+//! and `oneOf` together with a `discriminator`. Annotations are accepted
+//! and ignored, any other keyword is an error.
+
+use rustler::types::list::ListIterator;
+use rustler::types::map::MapIterator;
+use rustler::{Encoder, Env, Term};
+use std::collections::HashMap;
+
+use errors::push_pointer_key;
+use options::ParseOptions;
+
+use regex::Regex;
+
+use super::from_term::{new_nodes, read_bound};
+use super::{
+    Constraints, Format, Node, NodeId, NodeOptions, NodeVariant, Pattern, Spec, TagDefault,
+    ValueType,
+};
+
+mod atoms {
+    atoms! {
+        unsupported_keyword,
+        invalid_keyword,
+        invalid_schema,
+    }
+}
+
+const ANNOTATIONS: &'static [&'static str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+];
+
+const KEYWORDS: &'static [&'static str] = &[
+    "type",
+    "properties",
+    "required",
+    "items",
+    "enum",
+    "const",
+    "minimum",
+    "maximum",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "format",
+    "oneOf",
+    "discriminator",
+];
+
+const ALL_TYPES: &'static [ValueType] = &[
+    ValueType::Object,
+    ValueType::Array,
+    ValueType::String,
+    ValueType::Number,
+    ValueType::Boolean,
+    ValueType::Null,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaErrorReason {
+    /// A keyword outside the supported subset.
+    UnsupportedKeyword(String),
+    /// A supported keyword with a value that can not be used.
+    InvalidKeyword(String),
+    /// Something that is not a schema where one is expected.
+    InvalidSchema,
+}
+
+/// Why a schema could not be compiled. `path` is a JSON pointer into the
+/// schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    pub reason: SchemaErrorReason,
+    pub path: String,
+}
+
+impl SchemaError {
+    fn new(reason: SchemaErrorReason, path: &str) -> SchemaError {
+        SchemaError {
+            reason: reason,
+            path: path.to_string(),
+        }
+    }
+
+    /// `path` is that of the schema, the error points at the keyword.
+    fn invalid(keyword: &str, path: &str) -> SchemaError {
+        SchemaError::new(
+            SchemaErrorReason::InvalidKeyword(keyword.to_string()),
+            &keyword_path(path, keyword),
+        )
+    }
+
+    fn unsupported(keyword: &str, path: &str) -> SchemaError {
+        SchemaError::new(
+            SchemaErrorReason::UnsupportedKeyword(keyword.to_string()),
+            &keyword_path(path, keyword),
+        )
+    }
+
+    /// `{reason, keyword, path}`, with a keyword of `nil` for
+    /// `:invalid_schema`.
+    pub fn to_term<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self.reason {
+            SchemaErrorReason::UnsupportedKeyword(ref keyword) => (
+                atoms::unsupported_keyword(),
+                keyword.as_str(),
+                self.path.as_str(),
+            )
+                .encode(env),
+            SchemaErrorReason::InvalidKeyword(ref keyword) => (
+                atoms::invalid_keyword(),
+                keyword.as_str(),
+                self.path.as_str(),
+            )
+                .encode(env),
+            SchemaErrorReason::InvalidSchema => {
+                (atoms::invalid_schema(), ::atoms::nil(), self.path.as_str()).encode(env)
+            }
+        }
+    }
+}
+
+type SchemaResult<T> = Result<T, SchemaError>;
+
+/// The keywords of a schema object. A boolean schema is `None` when
+/// `true`.
+fn read_keywords<'a>(
+    schema: Term<'a>,
+    path: &str,
+) -> SchemaResult<Option<HashMap<String, Term<'a>>>> {
+    match schema.decode::<bool>() {
+        Ok(true) => return Ok(None),
+        // Nothing matches `false`, a spec can not say that.
+        Ok(false) => return Err(SchemaError::new(SchemaErrorReason::InvalidSchema, path)),
+        Err(_) => (),
+    }
+
+    let iterator = match schema.decode::<MapIterator>() {
+        Ok(iterator) => iterator,
+        Err(_) => return Err(SchemaError::new(SchemaErrorReason::InvalidSchema, path)),
+    };
+    let mut keywords = HashMap::new();
+    for (key, value) in iterator {
+        let key: String = match key.decode() {
+            Ok(key) => key,
+            Err(_) => return Err(SchemaError::new(SchemaErrorReason::InvalidSchema, path)),
+        };
+        if !KEYWORDS.contains(&key.as_str()) && !ANNOTATIONS.contains(&key.as_str()) {
+            return Err(SchemaError::unsupported(&key, path));
+        }
+        keywords.insert(key, value);
+    }
+    Ok(Some(keywords))
+}
+
+fn keyword_path(path: &str, keyword: &str) -> String {
+    let mut keyword_path = path.to_string();
+    push_pointer_key(&mut keyword_path, keyword.as_bytes());
+    keyword_path
+}
+
+fn read_type(name: &str) -> Option<(ValueType, bool)> {
+    match name {
+        "object" => Some((ValueType::Object, false)),
+        "array" => Some((ValueType::Array, false)),
+        "string" => Some((ValueType::String, false)),
+        "number" => Some((ValueType::Number, false)),
+        "integer" => Some((ValueType::Number, true)),
+        "boolean" => Some((ValueType::Boolean, false)),
+        "null" => Some((ValueType::Null, false)),
+        _ => None,
+    }
+}
+
+/// The types a schema allows, and whether numbers must be integers.
+fn read_types(
+    keywords: &HashMap<String, Term>,
+    path: &str,
+) -> SchemaResult<(Vec<ValueType>, bool)> {
+    let term = match keywords.get("type") {
+        Some(term) => *term,
+        None => return Ok((ALL_TYPES.to_vec(), false)),
+    };
+    let names: Vec<String> = match term.decode::<String>() {
+        Ok(name) => vec![name],
+        Err(_) => term
+            .decode()
+            .map_err(|_| SchemaError::invalid("type", path))?,
+    };
+
+    let mut types = Vec::new();
+    let mut integer = None;
+    for name in names {
+        let (typ, is_integer) = match read_type(&name) {
+            Some(read) => read,
+            None => return Err(SchemaError::invalid("type", path)),
+        };
+        if typ == ValueType::Number {
+            // `number` allows every integer as well.
+            integer = Some(integer.unwrap_or(true) && is_integer);
+        }
+        if !types.contains(&typ) {
+            types.push(typ);
+        }
+    }
+    Ok((types, integer.unwrap_or(false)))
+}
+
+/// The strings `enum` or `const` allow, and whether they allow `null`.
+/// Other values would need comparing whole terms, which no node does.
+fn read_values(
+    keywords: &HashMap<String, Term>,
+    path: &str,
+) -> SchemaResult<Option<(Vec<Vec<u8>>, bool)>> {
+    let (keyword, values): (&str, Vec<Term>) = match (keywords.get("enum"), keywords.get("const")) {
+        (Some(_), Some(_)) => return Err(SchemaError::invalid("const", path)),
+        (Some(term), None) => (
+            "enum",
+            term.decode::<ListIterator>()
+                .map_err(|_| SchemaError::invalid("enum", path))?
+                .collect(),
+        ),
+        (None, Some(term)) => ("const", vec![*term]),
+        (None, None) => return Ok(None),
+    };
+
+    let mut strings = Vec::new();
+    let mut null = false;
+    for value in values {
+        if let Ok(string) = value.decode::<String>() {
+            strings.push(string.into_bytes());
+        } else if ::atoms::nil() == value {
+            null = true;
+        } else {
+            return Err(SchemaError::unsupported(keyword, path));
+        }
+    }
+    Ok(Some((strings, null)))
+}
+
+fn read_constraints(keywords: &HashMap<String, Term>, path: &str) -> SchemaResult<Constraints> {
+    let mut constraints = Constraints::default();
+    for (keyword, term) in keywords {
+        let term = *term;
+        let invalid = |_| SchemaError::invalid(keyword, path);
+        match keyword.as_str() {
+            "minLength" => constraints.min_length = Some(term.decode().map_err(invalid)?),
+            "maxLength" => constraints.max_length = Some(term.decode().map_err(invalid)?),
+            "minimum" => constraints.min = Some(read_bound(term).map_err(invalid)?),
+            "maximum" => constraints.max = Some(read_bound(term).map_err(invalid)?),
+            "pattern" => {
+                let pattern: String = term.decode().map_err(invalid)?;
+                let regex =
+                    Regex::new(&pattern).map_err(|_| SchemaError::invalid(keyword, path))?;
+                constraints.pattern = Some(Pattern(regex));
+            }
+            "format" => {
+                let format: String = term.decode().map_err(invalid)?;
+                constraints.format = Some(match format.as_str() {
+                    "email" => Format::Email,
+                    "uuid" => Format::Uuid,
+                    "date-time" => Format::DateTime,
+                    _ => return Err(SchemaError::unsupported("format", path)),
+                });
+            }
+            _ => (),
+        }
+    }
+    Ok(constraints)
+}
+
+fn inherit(inherited: &NodeOptions) -> NodeOptions {
+    NodeOptions {
+        duplicate_keys: inherited.duplicate_keys,
+        objects: inherited.objects,
+        floats: inherited.floats,
+        ..NodeOptions::default()
+    }
+}
+
+fn push_node(
+    nodes: &mut Vec<Node>,
+    variant: NodeVariant,
+    options: NodeOptions,
+    parent: NodeId,
+) -> NodeId {
+    nodes.push(Node {
+        variant: variant,
+        options: options,
+        parent: Some(parent),
+    });
+    NodeId(nodes.len() - 1)
+}
+
+/// The node values of types a schema does not allow are matched to.
+/// Decodes them as is, with a violation.
+fn mismatch_node(
+    nodes: &mut Vec<Node>,
+    parent: NodeId,
+    inherited: &NodeOptions,
+    types: &[ValueType],
+) -> NodeId {
+    let mut options = inherit(inherited);
+    options.constraints.types = Some(types.to_vec());
+    push_node(nodes, NodeVariant::Any, options, parent)
+}
+
+fn read_object(
+    keywords: &HashMap<String, Term>,
+    nodes: &mut Vec<Node>,
+    parent: NodeId,
+    inherited: &NodeOptions,
+    path: &str,
+) -> SchemaResult<NodeId> {
+    let mut options = inherit(inherited);
+    if let Some(term) = keywords.get("required") {
+        let required: Vec<String> = term
+            .decode()
+            .map_err(|_| SchemaError::invalid("required", path))?;
+        options.required = required.into_iter().map(String::into_bytes).collect();
+    }
+    let child_inherited = options.clone();
+
+    let properties = match keywords.get("properties") {
+        Some(term) => term
+            .decode::<MapIterator>()
+            .map_err(|_| SchemaError::invalid("properties", path))?,
+        None => return Ok(push_node(nodes, NodeVariant::Any, options, parent)),
+    };
+
+    let current = push_node(nodes, NodeVariant::Sentinel, options, parent);
+    let properties_path = keyword_path(path, "properties");
+    let mut children = HashMap::<Vec<u8>, NodeId>::new();
+    for (key, value) in properties {
+        let key: String = key
+            .decode()
+            .map_err(|_| SchemaError::invalid("properties", path))?;
+        let child_path = keyword_path(&properties_path, &key);
+        let child = read_schema(value, nodes, current, &child_inherited, &child_path)?;
+        children.insert(key.into_bytes(), child);
+    }
+    nodes[current.0].variant = NodeVariant::MapKeys {
+        children: children,
+        default: None,
+    };
+    Ok(current)
+}
+
+fn read_array(
+    keywords: &HashMap<String, Term>,
+    nodes: &mut Vec<Node>,
+    parent: NodeId,
+    inherited: &NodeOptions,
+    path: &str,
+) -> SchemaResult<NodeId> {
+    let options = inherit(inherited);
+    let items = match keywords.get("items") {
+        Some(items) => *items,
+        None => return Ok(push_node(nodes, NodeVariant::Any, options, parent)),
+    };
+
+    let child_inherited = options.clone();
+    let current = push_node(nodes, NodeVariant::Sentinel, options, parent);
+    let child = read_schema(
+        items,
+        nodes,
+        current,
+        &child_inherited,
+        &keyword_path(path, "items"),
+    )?;
+    nodes[current.0].variant = NodeVariant::Array { child: child };
+    Ok(current)
+}
+
+/// `oneOf` is only supported as a tagged union, where `discriminator`
+/// names a property that each alternative fixes with `const` or `enum`.
+fn read_one_of(
+    keywords: &HashMap<String, Term>,
+    nodes: &mut Vec<Node>,
+    parent: NodeId,
+    inherited: &NodeOptions,
+    path: &str,
+) -> SchemaResult<NodeId> {
+    for keyword in keywords.keys() {
+        match keyword.as_str() {
+            "oneOf" | "discriminator" | "type" => (),
+            _ if ANNOTATIONS.contains(&keyword.as_str()) => (),
+            _ => return Err(SchemaError::unsupported(keyword, path)),
+        }
+    }
+    let typed = keywords.contains_key("type");
+    if typed && read_types(keywords, path)?.0 != [ValueType::Object] {
+        return Err(SchemaError::invalid("type", path));
+    }
+    // Takes the place of this node when values of other types are
+    // rejected.
+    let type_node = if typed {
+        Some(push_node(
+            nodes,
+            NodeVariant::Sentinel,
+            inherit(inherited),
+            parent,
+        ))
+    } else {
+        None
+    };
+
+    let discriminator_path = keyword_path(path, "discriminator");
+    let discriminator = match keywords.get("discriminator") {
+        Some(term) => read_keywords_raw(*term, &discriminator_path)?,
+        None => return Err(SchemaError::unsupported("oneOf", path)),
+    };
+    for keyword in discriminator.keys() {
+        if keyword != "propertyName" {
+            return Err(SchemaError::unsupported(keyword, &discriminator_path));
+        }
+    }
+    let key_name: String = match discriminator.get("propertyName").map(|term| term.decode()) {
+        Some(Ok(key_name)) => key_name,
+        _ => return Err(SchemaError::invalid("discriminator", path)),
+    };
+
+    let variants: Vec<Term> = keywords["oneOf"]
+        .decode::<ListIterator>()
+        .map_err(|_| SchemaError::invalid("oneOf", path))?
+        .collect();
+
+    let options = inherit(inherited);
+    let child_inherited = options.clone();
+    let current = push_node(nodes, NodeVariant::Sentinel, options, parent);
+
+    let one_of_path = keyword_path(path, "oneOf");
+    let mut mappings = HashMap::<Vec<u8>, NodeId>::new();
+    for (index, variant) in variants.into_iter().enumerate() {
+        let variant_path = format!("{}/{}", one_of_path, index);
+        let tags = variant_tags(variant, &key_name, &variant_path)?;
+        let child = read_schema(variant, nodes, current, &child_inherited, &variant_path)?;
+        for tag in tags {
+            mappings.insert(tag, child);
+        }
+    }
+
+    nodes[current.0].variant = NodeVariant::TaggedMap {
+        key_name: key_name.into_bytes(),
+        mappings: mappings,
+        default: TagDefault::Error,
+        streaming: Vec::new(),
+    };
+
+    match type_node {
+        Some(type_node) => {
+            let mismatch = mismatch_node(nodes, parent, inherited, &[ValueType::Object]);
+            let mut mappings = HashMap::<ValueType, NodeId>::new();
+            for &typ in ALL_TYPES {
+                mappings.insert(
+                    typ,
+                    if typ == ValueType::Object {
+                        current
+                    } else {
+                        mismatch
+                    },
+                );
+            }
+            nodes[type_node.0].variant = NodeVariant::Type { mappings: mappings };
+            Ok(type_node)
+        }
+        None => Ok(current),
+    }
+}
+
+/// Keys of an object that is not a schema.
+fn read_keywords_raw<'a>(term: Term<'a>, path: &str) -> SchemaResult<HashMap<String, Term<'a>>> {
+    let iterator = term
+        .decode::<MapIterator>()
+        .map_err(|_| SchemaError::new(SchemaErrorReason::InvalidSchema, path))?;
+    let mut keywords = HashMap::new();
+    for (key, value) in iterator {
+        let key: String = key
+            .decode()
+            .map_err(|_| SchemaError::new(SchemaErrorReason::InvalidSchema, path))?;
+        keywords.insert(key, value);
+    }
+    Ok(keywords)
+}
+
+/// The tags of a `oneOf` alternative, from its discriminator property.
+fn variant_tags(variant: Term, key_name: &str, path: &str) -> SchemaResult<Vec<Vec<u8>>> {
+    let property = read_keywords_raw(variant, path)?
+        .get("properties")
+        .and_then(|properties| read_keywords_raw(*properties, path).ok())
+        .and_then(|properties| properties.get(key_name).cloned());
+    let property_path = keyword_path(&keyword_path(path, "properties"), key_name);
+
+    let values = match property {
+        Some(property) => match read_keywords(property, &property_path)? {
+            Some(keywords) => read_values(&keywords, &property_path)?,
+            None => None,
+        },
+        None => None,
+    };
+    match values {
+        Some((ref tags, false)) if !tags.is_empty() => Ok(tags.clone()),
+        _ => Err(SchemaError::invalid("discriminator", path)),
+    }
+}
+
+/// Compiles a schema into nodes under `parent`. The first node pushed is
+/// the one for the schema itself.
+pub fn read_schema(
+    schema: Term,
+    nodes: &mut Vec<Node>,
+    parent: NodeId,
+    inherited: &NodeOptions,
+    path: &str,
+) -> SchemaResult<NodeId> {
+    let keywords = match read_keywords(schema, path)? {
+        Some(keywords) => keywords,
+        None => {
+            return Ok(push_node(
+                nodes,
+                NodeVariant::Any,
+                inherit(inherited),
+                parent,
+            ))
+        }
+    };
+    if keywords.contains_key("oneOf") {
+        return read_one_of(&keywords, nodes, parent, inherited, path);
+    }
+    if keywords.contains_key("discriminator") {
+        return Err(SchemaError::invalid("discriminator", path));
+    }
+
+    let (mut types, integer) = read_types(&keywords, path)?;
+    let mut constraints = read_constraints(&keywords, path)?;
+    constraints.integer = integer;
+    if let Some((strings, null)) = read_values(&keywords, path)? {
+        types.retain(|&typ| match typ {
+            ValueType::String => !strings.is_empty(),
+            ValueType::Null => null,
+            _ => false,
+        });
+        constraints.values = Some(strings);
+    }
+
+    let structured = keywords.contains_key("properties")
+        || keywords.contains_key("required")
+        || keywords.contains_key("items");
+    if types.len() == ALL_TYPES.len() && !structured {
+        let mut options = inherit(inherited);
+        options.constraints = constraints;
+        return Ok(push_node(nodes, NodeVariant::Any, options, parent));
+    }
+
+    // The nodes for each type take the place of this one.
+    let current = push_node(nodes, NodeVariant::Sentinel, inherit(inherited), parent);
+    let mut mappings = HashMap::<ValueType, NodeId>::new();
+    if types.len() < ALL_TYPES.len() {
+        let mismatch = mismatch_node(nodes, parent, inherited, &types);
+        for &typ in ALL_TYPES {
+            mappings.insert(typ, mismatch);
+        }
+    }
+    for typ in types {
+        let child = match typ {
+            ValueType::Object => read_object(&keywords, nodes, parent, inherited, path)?,
+            ValueType::Array => read_array(&keywords, nodes, parent, inherited, path)?,
+            _ => {
+                let mut options = inherit(inherited);
+                options.constraints = constraints.clone();
+                push_node(nodes, NodeVariant::Any, options, parent)
+            }
+        };
+        mappings.insert(typ, child);
+    }
+    nodes[current.0].variant = NodeVariant::Type { mappings: mappings };
+    Ok(current)
+}
+
+/// Compiles a schema into a spec. The parse options provide the defaults
+/// for inherited node options.
+pub fn spec_from_schema(schema: Term, parse_opts: &ParseOptions) -> Result<Spec, SchemaError> {
+    let mut nodes = new_nodes(parse_opts);
+    let sentinel_id = NodeId(0);
+    let sentinel_opts = nodes[0].options.clone();
+
+    assert_eq!(
+        read_schema(schema, &mut nodes, sentinel_id, &sentinel_opts, "")?,
+        NodeId(1)
+    );
+
+    Ok(Spec {
+        nodes: nodes,
+        root: sentinel_id,
+    })
+}
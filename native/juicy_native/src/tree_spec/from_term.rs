@@ -1,14 +1,26 @@
 use rustler::types::atom::Atom;
 use rustler::types::list::ListIterator;
 use rustler::types::map::MapIterator;
-use rustler::{Error, NifResult, Term};
+use rustler::{Encoder, Error, NifResult, Term};
 use std::collections::HashMap;
 
-use super::{Node, NodeId, NodeOptions, NodeVariant, Spec};
+use options::{DuplicateKeys, Floats, Objects, ParseOptions};
+
+use regex::Regex;
+
+use super::from_schema::read_schema;
+use super::{
+    Bound, Format, KeyDefault, Node, NodeId, NodeOptions, NodeVariant, Pattern, Spec, StoredTerm,
+    StreamCollect, TagDefault, ValueType,
+};
 
 mod atoms {
     atoms! {
         stream,
+        stream_collect,
+        placeholder,
+        drop,
+        count,
         any,
         map,
         map_keys,
@@ -16,17 +28,102 @@ mod atoms {
         struct_atom,
         atom_keys,
         ignore_non_atoms,
+        reject_non_atoms,
+        ignore_unknown,
+        duplicate_keys,
+        objects,
+        floats,
+        tuple,
+        as_tuple,
+        required,
+        defaults,
+        json_schema,
+        min_length,
+        max_length,
+        pattern,
+        format,
+        min,
+        max,
+        integer,
+        email,
+        uuid,
+        date_time,
+        enum_ = "enum",
+        default,
+        static_ = "static",
+        value,
+        raw,
+        tagged_map,
+        key_name,
+        error,
+        ignore,
+        node,
+        type_ = "type",
+        string,
+        number,
+        bool_ = "bool",
+        null,
+    }
+}
+
+/// Keys in options are given as atoms or binaries.
+fn read_key(term: Term) -> NifResult<Vec<u8>> {
+    if term.is_atom() {
+        Ok(term.atom_to_string()?.into_bytes())
+    } else {
+        let key: String = term.decode()?;
+        Ok(key.into_bytes())
+    }
+}
+
+pub fn read_bound(term: Term) -> NifResult<Bound> {
+    match term.decode::<i64>() {
+        Ok(integer) => Ok(Bound(integer as f64)),
+        Err(_) => Ok(Bound(term.decode::<f64>()?)),
+    }
+}
+
+fn read_stream_collect(term: Term) -> NifResult<StreamCollect> {
+    if atoms::placeholder() == term {
+        Ok(StreamCollect::Placeholder)
+    } else if atoms::drop() == term {
+        Ok(StreamCollect::Drop)
+    } else if atoms::count() == term {
+        Ok(StreamCollect::Count)
+    } else {
+        Err(Error::BadArg)
+    }
+}
+
+fn read_format(term: Term) -> NifResult<Format> {
+    if atoms::email() == term {
+        Ok(Format::Email)
+    } else if atoms::uuid() == term {
+        Ok(Format::Uuid)
+    } else if atoms::date_time() == term {
+        Ok(Format::DateTime)
+    } else {
+        Err(Error::BadArg)
     }
 }
 
-fn read_opts(term: Term, stream_collect: bool) -> NifResult<NodeOptions> {
+/// Options that are not set on a node are taken from `inherited`, the
+/// options of its parent.
+fn read_opts(term: Term, inherited: &NodeOptions) -> NifResult<NodeOptions> {
     let iterator: ListIterator = term.decode()?;
-    let mut opts = NodeOptions::default();
+    let mut opts = NodeOptions {
+        duplicate_keys: inherited.duplicate_keys,
+        objects: inherited.objects,
+        floats: inherited.floats,
+        ..NodeOptions::default()
+    };
     for decoded in iterator.map(|term| term.decode::<(Term, Term)>()) {
         let (key, value) = decoded?;
 
         if atoms::stream() == key {
             opts.stream = value.decode()?;
+        } else if atoms::stream_collect() == key {
+            opts.stream_collect = read_stream_collect(value)?;
         } else if atoms::struct_atom() == key {
             opts.struct_atom = Some(value.decode()?);
         } else if atoms::atom_keys() == key {
@@ -40,25 +137,134 @@ fn read_opts(term: Term, stream_collect: bool) -> NifResult<NodeOptions> {
             opts.atom_mappings = Some(map);
         } else if atoms::ignore_non_atoms() == key {
             opts.ignore_non_atoms = value.decode()?;
+        } else if atoms::reject_non_atoms() == key {
+            opts.reject_non_atoms = value.decode()?;
+        } else if atoms::ignore_unknown() == key {
+            opts.ignore_unknown = value.decode()?;
+        } else if atoms::duplicate_keys() == key {
+            opts.duplicate_keys = value.decode::<DuplicateKeys>()?;
+        } else if atoms::objects() == key {
+            opts.objects = value.decode::<Objects>()?;
+        } else if atoms::floats() == key {
+            opts.floats = value.decode::<Floats>()?;
+        } else if atoms::as_tuple() == key {
+            opts.as_tuple = value.decode()?;
+        } else if atoms::min_length() == key {
+            opts.constraints.min_length = Some(value.decode()?);
+        } else if atoms::max_length() == key {
+            opts.constraints.max_length = Some(value.decode()?);
+        } else if atoms::pattern() == key {
+            let pattern: String = value.decode()?;
+            let regex = Regex::new(&pattern).map_err(|_| Error::BadArg)?;
+            opts.constraints.pattern = Some(Pattern(regex));
+        } else if atoms::format() == key {
+            opts.constraints.format = Some(read_format(value)?);
+        } else if atoms::min() == key {
+            opts.constraints.min = Some(read_bound(value)?);
+        } else if atoms::max() == key {
+            opts.constraints.max = Some(read_bound(value)?);
+        } else if atoms::integer() == key {
+            opts.constraints.integer = value.decode()?;
+        } else if atoms::required() == key {
+            let iterator: ListIterator = value.decode()?;
+            for key_term in iterator {
+                opts.required.push(read_key(key_term)?);
+            }
+        } else if atoms::defaults() == key {
+            for (key_term, default) in value.decode::<MapIterator>()? {
+                opts.defaults.push(KeyDefault {
+                    key: StoredTerm::new(key_term),
+                    key_bytes: read_key(key_term)?,
+                    value: StoredTerm::new(default),
+                });
+            }
         }
     }
-    opts.stream_collect = opts.stream | stream_collect;
+    if opts.struct_atom.is_some() {
+        opts.objects = Objects::Maps;
+    }
     Ok(opts)
 }
 
+/// Looks up an option that only some node types take.
+fn find_opt<'a>(opts: Term<'a>, name: Atom) -> NifResult<Option<Term<'a>>> {
+    let iterator: ListIterator = opts.decode()?;
+    for decoded in iterator.map(|term| term.decode::<(Term, Term)>()) {
+        let (key, value) = decoded?;
+        if name == key {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// Enum values are either a map of strings to atoms, or a list of
+/// strings or atoms that map to the atom of the same name.
+fn read_enum_values(term: Term) -> NifResult<HashMap<Vec<u8>, Atom>> {
+    let mut values = HashMap::new();
+    if let Ok(iterator) = term.decode::<MapIterator>() {
+        for (key, value) in iterator {
+            let string: String = key.decode()?;
+            values.insert(string.into_bytes(), value.decode::<Atom>()?);
+        }
+        return Ok(values);
+    }
+
+    for value in term.decode::<ListIterator>()? {
+        if let Ok(atom) = value.decode::<Atom>() {
+            values.insert(value.atom_to_string()?.into_bytes(), atom);
+        } else {
+            // Atoms are created from latin1, so only ASCII strings map
+            // to the atom of the same name.
+            let string: String = value.decode()?;
+            if !string.is_ascii() {
+                return Err(Error::BadArg);
+            }
+            let atom = Atom::from_bytes(term.get_env(), string.as_bytes())?;
+            values.insert(string.into_bytes(), atom);
+        }
+    }
+    Ok(values)
+}
+
+/// Whether any of the nodes from `first` on, the ones read for a
+/// single child, is streamed.
+fn streams_below(nodes: &[Node], first: usize) -> bool {
+    nodes[first..].iter().any(|node| node.options.stream)
+}
+
+fn read_value_type(term: Term) -> NifResult<ValueType> {
+    if atoms::map() == term {
+        Ok(ValueType::Object)
+    } else if atoms::array() == term {
+        Ok(ValueType::Array)
+    } else if atoms::string() == term {
+        Ok(ValueType::String)
+    } else if atoms::number() == term {
+        Ok(ValueType::Number)
+    } else if atoms::bool_() == term {
+        Ok(ValueType::Boolean)
+    } else if atoms::null() == term {
+        Ok(ValueType::Null)
+    } else {
+        Err(Error::BadArg)
+    }
+}
+
 fn read_node(
     node: Term,
     nodes: &mut Vec<Node>,
     parent: NodeId,
-    stream_collect: bool,
+    inherited: &NodeOptions,
 ) -> NifResult<NodeId> {
     let current = NodeId(nodes.len());
 
     // Arity 3
     match node.decode::<(Term, Term, Term)>() {
         Ok((typ, opts, data)) => {
-            let opts = read_opts(opts, stream_collect)?;
-            let child_stream_collect = opts.stream_collect;
+            let opts_term = opts;
+            let opts = read_opts(opts, inherited)?;
+            let child_inherited = opts.clone();
 
             return if atoms::map() == typ {
                 nodes.push(Node {
@@ -67,7 +273,7 @@ fn read_node(
                     parent: Some(parent),
                 });
 
-                let child = read_node(data, nodes, current, child_stream_collect)?;
+                let child = read_node(data, nodes, current, &child_inherited)?;
                 nodes[current.0].variant = NodeVariant::Map { child: child };
 
                 Ok(current)
@@ -78,12 +284,20 @@ fn read_node(
                     parent: Some(parent),
                 });
 
-                let mut children = HashMap::<String, NodeId>::new();
+                let mut children = HashMap::<Vec<u8>, NodeId>::new();
                 for (key, value) in data.decode::<MapIterator>()? {
-                    let child = read_node(value, nodes, current, child_stream_collect)?;
-                    children.insert(key.decode()?, child);
+                    let child = read_node(value, nodes, current, &child_inherited)?;
+                    let key: String = key.decode()?;
+                    children.insert(key.into_bytes(), child);
                 }
-                nodes[current.0].variant = NodeVariant::MapKeys { children: children };
+                let default = match find_opt(opts_term, atoms::default())? {
+                    Some(default) => Some(read_node(default, nodes, current, &child_inherited)?),
+                    None => None,
+                };
+                nodes[current.0].variant = NodeVariant::MapKeys {
+                    children: children,
+                    default: default,
+                };
 
                 Ok(current)
             } else if atoms::array() == typ {
@@ -93,9 +307,100 @@ fn read_node(
                     parent: Some(parent),
                 });
 
-                let child = read_node(data, nodes, current, child_stream_collect)?;
+                let child = read_node(data, nodes, current, &child_inherited)?;
                 nodes[current.0].variant = NodeVariant::Array { child: child };
 
+                Ok(current)
+            } else if atoms::tuple() == typ {
+                nodes.push(Node {
+                    variant: NodeVariant::Sentinel,
+                    options: opts,
+                    parent: Some(parent),
+                });
+
+                let mut children = Vec::<NodeId>::new();
+                for child_term in data.decode::<ListIterator>()? {
+                    children.push(read_node(child_term, nodes, current, &child_inherited)?);
+                }
+                nodes[current.0].variant = NodeVariant::Tuple { children: children };
+
+                Ok(current)
+            } else if atoms::tagged_map() == typ {
+                let key_name: String = match find_opt(opts_term, atoms::key_name())? {
+                    Some(key_name) => key_name.decode()?,
+                    None => return Err(Error::BadArg),
+                };
+                nodes.push(Node {
+                    variant: NodeVariant::Sentinel,
+                    options: opts,
+                    parent: Some(parent),
+                });
+
+                let mut mappings = HashMap::<Vec<u8>, NodeId>::new();
+                let mut streaming = Vec::<NodeId>::new();
+                for (tag, value) in data.decode::<MapIterator>()? {
+                    let tag: String = tag.decode()?;
+                    let first = nodes.len();
+                    let child = read_node(value, nodes, current, &child_inherited)?;
+                    if streams_below(nodes, first) {
+                        streaming.push(child);
+                    }
+                    mappings.insert(tag.into_bytes(), child);
+                }
+
+                let default = match find_opt(opts_term, atoms::default())? {
+                    None => TagDefault::Error,
+                    Some(term) if atoms::error() == term => TagDefault::Error,
+                    Some(term) if atoms::ignore() == term => TagDefault::Ignore,
+                    Some(term) => {
+                        let (tag, child): (Atom, Term) = term.decode()?;
+                        if atoms::node() != tag {
+                            return Err(Error::BadArg);
+                        }
+                        let first = nodes.len();
+                        let child = read_node(child, nodes, current, &child_inherited)?;
+                        if streams_below(nodes, first) {
+                            streaming.push(child);
+                        }
+                        TagDefault::Node(child)
+                    }
+                };
+
+                nodes[current.0].variant = NodeVariant::TaggedMap {
+                    key_name: key_name.into_bytes(),
+                    mappings: mappings,
+                    default: default,
+                    streaming: streaming,
+                };
+
+                Ok(current)
+            } else if atoms::type_() == typ {
+                nodes.push(Node {
+                    variant: NodeVariant::Sentinel,
+                    options: opts,
+                    parent: Some(parent),
+                });
+
+                // The picked node takes the place of this one.
+                let mut mappings = HashMap::<ValueType, NodeId>::new();
+                for (value_type, value) in data.decode::<MapIterator>()? {
+                    let child = read_node(value, nodes, parent, &child_inherited)?;
+                    mappings.insert(read_value_type(value_type)?, child);
+                }
+                nodes[current.0].variant = NodeVariant::Type { mappings: mappings };
+
+                Ok(current)
+            } else if atoms::enum_() == typ {
+                let default = find_opt(opts_term, atoms::default())?.map(StoredTerm::new);
+                nodes.push(Node {
+                    variant: NodeVariant::Enum {
+                        values: read_enum_values(data)?,
+                        default: default,
+                    },
+                    options: opts,
+                    parent: Some(parent),
+                });
+
                 Ok(current)
             } else {
                 Err(Error::BadArg)
@@ -107,7 +412,13 @@ fn read_node(
     // Arity 2
     match node.decode::<(Term, Term)>() {
         Ok((typ, opts)) => {
-            let opts = read_opts(opts, stream_collect)?;
+            // Takes a schema in place of options.
+            if atoms::json_schema() == typ {
+                return read_schema(opts, nodes, parent, inherited, "").map_err(|_| Error::BadArg);
+            }
+
+            let opts_term = opts;
+            let opts = read_opts(opts, inherited)?;
 
             return if atoms::any() == typ {
                 nodes.push(Node {
@@ -116,6 +427,37 @@ fn read_node(
                     parent: Some(parent),
                 });
                 Ok(current)
+            } else if atoms::static_() == typ {
+                let value = match find_opt(opts_term, atoms::value())? {
+                    Some(value) => value,
+                    None => ::atoms::nil().encode(node.get_env()),
+                };
+                nodes.push(Node {
+                    variant: NodeVariant::Static {
+                        value: StoredTerm::new(value),
+                    },
+                    options: opts,
+                    parent: Some(parent),
+                });
+                Ok(current)
+            } else if atoms::ignore() == typ {
+                nodes.push(Node {
+                    variant: NodeVariant::Ignore,
+                    options: opts,
+                    parent: Some(parent),
+                });
+                Ok(current)
+            } else if atoms::value() == typ {
+                let raw = match find_opt(opts_term, atoms::raw())? {
+                    Some(raw) => raw.decode()?,
+                    None => false,
+                };
+                nodes.push(Node {
+                    variant: NodeVariant::Value { raw: raw },
+                    options: opts,
+                    parent: Some(parent),
+                });
+                Ok(current)
             } else {
                 Err(Error::BadArg)
             };
@@ -126,18 +468,32 @@ fn read_node(
     Err(Error::BadArg)
 }
 
-pub fn spec_from_term(root: Term) -> NifResult<Spec> {
-    let mut nodes = Vec::<Node>::new();
-
-    let sentinel = Node {
+/// A node list holding just the sentinel. The parse options provide the
+/// defaults for inherited node options.
+pub fn new_nodes(parse_opts: &ParseOptions) -> Vec<Node> {
+    let sentinel_opts = NodeOptions {
+        duplicate_keys: parse_opts.duplicate_keys,
+        objects: parse_opts.objects,
+        floats: parse_opts.floats,
+        ..NodeOptions::default()
+    };
+    vec![Node {
         variant: NodeVariant::Sentinel,
-        options: NodeOptions::default(),
+        options: sentinel_opts,
         parent: None,
-    };
-    nodes.push(sentinel);
+    }]
+}
+
+/// The parse options provide the defaults for inherited node options.
+pub fn spec_from_term(root: Term, parse_opts: &ParseOptions) -> NifResult<Spec> {
+    let mut nodes = new_nodes(parse_opts);
     let sentinel_id = NodeId(0);
+    let sentinel_opts = nodes[0].options.clone();
 
-    assert_eq!(read_node(root, &mut nodes, sentinel_id, false)?, NodeId(1));
+    assert_eq!(
+        read_node(root, &mut nodes, sentinel_id, &sentinel_opts)?,
+        NodeId(1)
+    );
 
     Ok(Spec {
         nodes: nodes,
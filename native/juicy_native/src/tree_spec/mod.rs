@@ -1,12 +1,29 @@
 use rustler::types::atom::Atom;
+use rustler::{Env, Term};
 use std::collections::HashMap;
 
+use options::{DuplicateKeys, Floats, Objects};
+
+mod compiled;
+mod constraints;
+mod from_schema;
 mod from_term;
 mod walker;
 
+pub use self::compiled::{compile_schema, compile_spec, read_spec, CompiledSpec};
+pub use self::constraints::{Bound, Constraints, Format, Pattern};
+pub use self::from_schema::{spec_from_schema, SchemaError};
 pub use self::from_term::spec_from_term;
 pub use self::walker::{PathEntry, PathPosition, SpecWalker};
 
+/// Errors raised while walking a document with a spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpecError {
+    /// The spec uses a feature that is not supported at this point in
+    /// the document.
+    Unsupported(&'static str),
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ValueType {
     Object,
@@ -17,23 +34,119 @@ pub enum ValueType {
     Null,
 }
 
+impl ValueType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueType::Object => "object",
+            ValueType::Array => "array",
+            ValueType::String => "string",
+            ValueType::Number => "number",
+            ValueType::Boolean => "boolean",
+            ValueType::Null => "null",
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct NodeId(usize);
 
+/// A term held by a spec. Specs outlive the env they were read in, so
+/// the term is kept in the external term format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredTerm(Vec<u8>);
+impl StoredTerm {
+    pub fn new(term: Term) -> StoredTerm {
+        StoredTerm(term.to_binary().as_slice().to_vec())
+    }
+
+    pub fn load<'a>(&self, env: Env<'a>) -> Term<'a> {
+        // Written by `new`, so decoding can not fail.
+        env.binary_to_term(&self.0).unwrap().0
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Node {
     pub variant: NodeVariant,
     pub options: NodeOptions,
     pub parent: Option<NodeId>,
 }
+impl Node {
+    pub fn is_enum(&self) -> bool {
+        match self.variant {
+            NodeVariant::Enum { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_ignore(&self) -> bool {
+        self.variant == NodeVariant::Ignore
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum NodeVariant {
     Sentinel,
     Any,
-    Array { child: NodeId },
-    Map { child: NodeId },
-    MapKeys { children: HashMap<String, NodeId> },
+    Array {
+        child: NodeId,
+    },
+    Map {
+        child: NodeId,
+    },
+    /// An object with a node per key. Keys without one use `default`,
+    /// or are left off the spec without it.
+    MapKeys {
+        children: HashMap<Vec<u8>, NodeId>,
+        default: Option<NodeId>,
+    },
+    Tuple {
+        children: Vec<NodeId>,
+    },
+    /// A string that is one of a fixed set, decoded to an atom. Other
+    /// values decode to `default`, or fail without one.
+    Enum {
+        values: HashMap<Vec<u8>, Atom>,
+        default: Option<StoredTerm>,
+    },
+    /// Always decodes to `value`, whatever the document holds.
+    Static {
+        value: StoredTerm,
+    },
+    /// Any value, decoded as is. With `raw` the source text of the value
+    /// is kept instead.
+    Value {
+        raw: bool,
+    },
+    /// Any value, only checked to be valid JSON. No terms are built for
+    /// it. Left out of objects, `nil` anywhere else.
+    Ignore,
+    /// An object decoded with the node its `key_name` key maps to. The
+    /// key may come last, so the object is decoded again from its source
+    /// once the tag is known.
+    TaggedMap {
+        key_name: Vec<u8>,
+        mappings: HashMap<Vec<u8>, NodeId>,
+        default: TagDefault,
+        /// The nodes it picks from that have a stream at or below them.
+        /// The object is decoded in one go, so those can not be streamed.
+        streaming: Vec<NodeId>,
+    },
+    /// Picks a node by the type of the value. The nodes it picks from
+    /// have the parent of this node as their parent, so once a node is
+    /// picked this one is out of the way.
+    Type {
+        mappings: HashMap<ValueType, NodeId>,
+    },
+}
+
+/// What a `tagged_map` does with an object whose tag has no mapping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagDefault {
+    Error,
+    /// Keep the object as decoded without a spec.
+    Ignore,
+    Node(NodeId),
 }
 
 impl NodeVariant {
@@ -44,6 +157,13 @@ impl NodeVariant {
             &NodeVariant::Map { .. } if value == ValueType::Object => true,
             &NodeVariant::MapKeys { .. } if value == ValueType::Object => true,
             &NodeVariant::Array { .. } if value == ValueType::Array => true,
+            &NodeVariant::Tuple { .. } if value == ValueType::Array => true,
+            &NodeVariant::Enum { .. } => true,
+            &NodeVariant::Static { .. } => true,
+            &NodeVariant::Value { .. } => true,
+            &NodeVariant::Ignore => true,
+            &NodeVariant::TaggedMap { .. } if value == ValueType::Object => true,
+            &NodeVariant::Type { ref mappings } => mappings.contains_key(&value),
             _ => false,
         }
     }
@@ -56,43 +176,111 @@ impl NodeVariant {
         }
     }
 
-    pub fn child_key(&self, _key: &[u8]) -> Option<NodeId> {
+    pub fn child_key(&self, key: &[u8]) -> Result<Option<NodeId>, SpecError> {
         match self {
             &NodeVariant::Sentinel => unreachable!(),
-            &NodeVariant::Any => None,
-            &NodeVariant::Map { child } => Some(child),
-            &NodeVariant::Array { .. } => None,
-            _ => unimplemented!(),
+            &NodeVariant::Any => Ok(None),
+            &NodeVariant::Map { child } => Ok(Some(child)),
+            &NodeVariant::MapKeys {
+                ref children,
+                default,
+            } => Ok(children.get(key).cloned().or(default)),
+            &NodeVariant::Array { .. } => Ok(None),
+            &NodeVariant::Tuple { .. } => Ok(None),
+            &NodeVariant::Enum { .. } => Ok(None),
+            &NodeVariant::Static { .. } => Ok(None),
+            &NodeVariant::Value { .. } => Ok(None),
+            &NodeVariant::Ignore => Ok(None),
+            &NodeVariant::TaggedMap { .. } => Ok(None),
+            &NodeVariant::Type { .. } => Err(SpecError::Unsupported("type node as container")),
         }
     }
 
-    pub fn child_index(&self, _index: usize) -> Option<NodeId> {
+    pub fn child_index(&self, index: usize) -> Result<Option<NodeId>, SpecError> {
         match self {
             &NodeVariant::Sentinel => unreachable!(),
-            &NodeVariant::Any => None,
-            &NodeVariant::Map { .. } => None,
-            &NodeVariant::Array { child } => Some(child),
-            _ => unimplemented!(),
+            &NodeVariant::Any => Ok(None),
+            &NodeVariant::Map { .. } => Ok(None),
+            &NodeVariant::MapKeys { .. } => Ok(None),
+            &NodeVariant::Array { child } => Ok(Some(child)),
+            // Extra elements are left off the spec, the arity is checked
+            // once the array ends.
+            &NodeVariant::Tuple { ref children } => Ok(children.get(index).cloned()),
+            &NodeVariant::Enum { .. } => Ok(None),
+            &NodeVariant::Static { .. } => Ok(None),
+            &NodeVariant::Value { .. } => Ok(None),
+            &NodeVariant::Ignore => Ok(None),
+            &NodeVariant::TaggedMap { .. } => Ok(None),
+            &NodeVariant::Type { .. } => Err(SpecError::Unsupported("type node as container")),
         }
     }
 }
 
+/// A key a map node fills in when the object does not have it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyDefault {
+    /// The key as given in the spec, an atom or a binary.
+    pub key: StoredTerm,
+    pub key_bytes: Vec<u8>,
+    pub value: StoredTerm,
+}
+
+/// What a container keeps in place of its children that are streamed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StreamCollect {
+    /// A `:streamed` atom per child.
+    Placeholder,
+    /// Nothing, the children are left out.
+    Drop,
+    /// The container decodes to the number of children that were
+    /// streamed. Its other children are left out.
+    Count,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NodeOptions {
     pub stream: bool,
-    pub stream_collect: bool,
+    /// Applies to the children of this node, not inherited.
+    pub stream_collect: StreamCollect,
     pub struct_atom: Option<Atom>,
     pub atom_mappings: Option<HashMap<Vec<u8>, Atom>>,
+    /// Keys without an entry in `atom_mappings` are left out.
     pub ignore_non_atoms: bool,
+    /// Keys without an entry in `atom_mappings` are an error.
+    pub reject_non_atoms: bool,
+    /// Keys with no node below this one are skipped like `ignore` nodes.
+    pub ignore_unknown: bool,
+    /// Inherited by child nodes unless they set their own.
+    pub duplicate_keys: DuplicateKeys,
+    /// Inherited. Always `Maps` on nodes with a `struct_atom`.
+    pub objects: Objects,
+    /// Inherited.
+    pub floats: Floats,
+    /// Decode a `tuple` node into an Elixir tuple instead of a list.
+    pub as_tuple: bool,
+    /// Keys an object must have, compared by name whether they decode
+    /// to atoms or binaries.
+    pub required: Vec<Vec<u8>>,
+    pub defaults: Vec<KeyDefault>,
+    pub constraints: Constraints,
 }
 impl Default for NodeOptions {
     fn default() -> Self {
         NodeOptions {
             stream: false,
-            stream_collect: false,
+            stream_collect: StreamCollect::Placeholder,
             struct_atom: None,
             atom_mappings: None,
             ignore_non_atoms: false,
+            reject_non_atoms: false,
+            ignore_unknown: false,
+            duplicate_keys: DuplicateKeys::Last,
+            objects: Objects::Maps,
+            floats: Floats::Native,
+            as_tuple: false,
+            required: Vec::new(),
+            defaults: Vec::new(),
+            constraints: Constraints::default(),
         }
     }
 }
@@ -1,10 +1,10 @@
-use super::{NodeId, Spec, ValueType};
+use super::{NodeId, NodeVariant, Spec, SpecError, ValueType};
 
-use rustler::types::binary::OwnedBinary;
 use rustler::{Encoder, Env, Term};
-use std::io::Write;
 
-#[derive(Debug)]
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
 pub enum PathEntry {
     Key(Vec<u8>),
     /// The index field is 1 indexed so that 0 can be used as a sentinel value.
@@ -16,11 +16,9 @@ impl Encoder for PathEntry {
     fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
         match self {
             &PathEntry::Index(idx) => ((idx - 1) as u64).encode(env),
-            &PathEntry::Key(ref key) => {
-                let mut bin = OwnedBinary::new(key.len()).unwrap();
-                bin.as_mut_slice().write(key).unwrap();
-                bin.release(env).encode(env)
-            }
+            // Keys are valid UTF-8 so nothing is replaced, the key is copied
+            // into a new binary.
+            &PathEntry::Key(ref key) => String::from_utf8_lossy(key).as_ref().encode(env),
         }
     }
 }
@@ -42,10 +40,13 @@ impl PathEntry {
 
 #[derive(Debug)]
 pub struct SpecWalker {
-    pub spec: Spec,
+    pub spec: Arc<Spec>,
     current: NodeId,
     depth: usize,
     height_off_current: usize,
+    /// Node the outermost value is matched against instead of the
+    /// root of the spec.
+    root: Option<NodeId>,
 }
 
 pub struct PathPosition {
@@ -54,56 +55,92 @@ pub struct PathPosition {
 }
 
 impl SpecWalker {
-    pub fn new(spec: Spec) -> SpecWalker {
+    pub fn new(spec: Arc<Spec>) -> SpecWalker {
         SpecWalker {
             current: spec.root_id(),
             spec: spec,
 
             depth: 0,
             height_off_current: 0,
+            root: None,
+        }
+    }
+
+    /// A walker for a value somewhere inside a document, matched against
+    /// `root`.
+    pub fn new_at(spec: Arc<Spec>, root: NodeId) -> SpecWalker {
+        SpecWalker {
+            current: spec.get(root).parent.unwrap(),
+            spec: spec,
+
+            depth: 0,
+            height_off_current: 0,
+            root: Some(root),
         }
     }
 
-    fn try_child(&self, typ: ValueType, key: Option<&PathEntry>) -> PathPosition {
+    fn try_child(
+        &self,
+        typ: ValueType,
+        key: Option<&PathEntry>,
+    ) -> Result<PathPosition, SpecError> {
         match self.height_off_current {
             0 => {
                 let current = self.spec.get(self.current);
 
-                let child_node_id = match key {
-                    None => current.variant.child_root(),
-                    Some(&PathEntry::Index(index)) => current.variant.child_index(index),
-                    Some(&PathEntry::Key(ref name)) => current.variant.child_key(&name),
+                let child_node_id = match (self.root, key) {
+                    (Some(root), _) if self.depth == 0 => Some(root),
+                    (_, None) => current.variant.child_root(),
+                    (_, Some(&PathEntry::Index(index))) => current.variant.child_index(index)?,
+                    (_, Some(&PathEntry::Key(ref name))) => current.variant.child_key(&name)?,
                 };
 
                 let child_node_match = match child_node_id {
-                    Some(child_id) => {
-                        let child = self.spec.get(child_id);
-                        if child.variant.matches(typ) {
-                            Some(child_id)
-                        } else {
-                            None
-                        }
-                    }
+                    Some(child_id) => self.resolve(child_id, typ),
                     None => None,
                 };
 
-                PathPosition {
+                Ok(PathPosition {
                     current: child_node_match,
                     parent: Some(self.current),
-                }
+                })
             }
-            _ => PathPosition {
+            _ => Ok(PathPosition {
                 current: None,
                 parent: None,
-            },
+            }),
+        }
+    }
+
+    /// The node a value of type `typ` is matched to at `node_id`,
+    /// following `type` nodes.
+    fn resolve(&self, node_id: NodeId, typ: ValueType) -> Option<NodeId> {
+        let node = self.spec.get(node_id);
+        match node.variant {
+            NodeVariant::Type { ref mappings } => mappings
+                .get(&typ)
+                .and_then(|&child| self.resolve(child, typ)),
+            ref variant if variant.matches(typ) => Some(node_id),
+            _ => None,
         }
     }
 
-    pub fn visit_terminal(&mut self, typ: ValueType, key: Option<&PathEntry>) -> PathPosition {
+    pub fn visit_terminal(
+        &mut self,
+        typ: ValueType,
+        key: Option<&PathEntry>,
+    ) -> Result<PathPosition, SpecError> {
         self.try_child(typ, key)
     }
 
-    pub fn visit_key(&self) -> Option<NodeId> {
+    /// The innermost node on the spec that encloses the current position.
+    pub fn nearest(&self) -> NodeId {
+        self.current
+    }
+
+    /// The node of the innermost container, if that container is on the
+    /// spec.
+    pub fn container(&self) -> Option<NodeId> {
         if self.height_off_current == 0 {
             Some(self.current)
         } else {
@@ -111,12 +148,16 @@ impl SpecWalker {
         }
     }
 
+    pub fn visit_key(&self) -> Option<NodeId> {
+        self.container()
+    }
+
     pub fn enter_nonterminal<'a>(
         &mut self,
         typ: ValueType,
         key: Option<&PathEntry>,
-    ) -> PathPosition {
-        let resp = self.try_child(typ, key);
+    ) -> Result<PathPosition, SpecError> {
+        let resp = self.try_child(typ, key)?;
 
         match resp.current {
             Some(child_id) => {
@@ -128,7 +169,7 @@ impl SpecWalker {
             }
         }
 
-        resp
+        Ok(resp)
     }
 
     pub fn exit_nonterminal(&mut self) -> PathPosition {
@@ -37,20 +37,168 @@ defmodule JuicyTest do
     assert p("[1.0, 1.0e0, -1e-1]") == {:ok, [1.0, 1.0, -0.1]}
   end
 
+  test "floats as decimals and strings" do
+    input = "[1.50, -12.5e-3, 2E+2, 10]"
+    assert Juicy.parse(input, floats: :decimal) == {:ok, [
+      %{__struct__: Decimal, sign: 1, coef: 150, exp: -2},
+      %{__struct__: Decimal, sign: -1, coef: 125, exp: -4},
+      %{__struct__: Decimal, sign: 1, coef: 2, exp: 2},
+      10,
+    ]}
+    assert Juicy.parse("[0.1000000000000000000000000001]", floats: :decimal) ==
+      {:ok, [%{__struct__: Decimal, sign: 1, coef: 1000000000000000000000000001, exp: -28}]}
+    assert Juicy.parse(input, floats: :string) == {:ok, ["1.50", "-12.5e-3", "2e2", 10]}
+
+    spec = {:array, [floats: :string], {:any, []}}
+    assert Juicy.parse_spec("[1.5]", spec) == {:ok, ["1.5"]}
+    spec = {:array, [], {:any, [floats: :string]}}
+    assert Juicy.parse_spec("[1.5]", spec) == {:ok, ["1.5"]}
+  end
+
   test "large integers" do
     input = ~s([9999999999999999999999999999999999999999])
     output = {:ok, [9999999999999999999999999999999999999999]}
     assert p(input) == output
   end
 
+  test "parse errors" do
+    assert {:error, %Juicy.Error{} = error} = p(~s({"a": [1,\n  2, x]}))
+    assert error.offset == 15
+    assert error.line == 2
+    assert error.column == 6
+    assert error.path == "/a/2"
+    assert is_atom(error.reason)
+    assert error.snippet == ~s({"a": [1,\n  2, x]})
+
+    assert {:error, %Juicy.Error{path: "/a~1b/0"}} = p(~s({"a/b": [}))
+  end
+
+  test "duplicate keys" do
+    input = ~s({"a": 1, "b": {"c": 2, "c": 3}, "a": 4})
+    assert p(input) == {:ok, %{"a" => 4, "b" => %{"c" => 3}}}
+    assert Juicy.parse(input, duplicate_keys: :last) == p(input)
+    assert Juicy.parse(input, duplicate_keys: :first) ==
+      {:ok, %{"a" => 1, "b" => %{"c" => 2}}}
+
+    assert {:error, %Juicy.Error{reason: :duplicate_key, path: "/b/c"}} =
+      Juicy.parse(input, duplicate_keys: :error)
+  end
+
+  test "atom keys" do
+    input = ~s({"some": {"juicy_unknown_key_1": 1}})
+    assert Juicy.parse(input, keys: :strings) ==
+      {:ok, %{"some" => %{"juicy_unknown_key_1" => 1}}}
+    assert Juicy.parse(input, keys: :existing_atoms) ==
+      {:ok, %{some: %{"juicy_unknown_key_1" => 1}}}
+    assert {:error, %Juicy.Error{reason: :missing_atom, path: "/some/juicy_unknown_key_1"}} =
+      Juicy.parse(input, keys: {:existing_atoms, :error})
+    assert Juicy.parse(input, keys: {:intern, [:thing]}) ==
+      {:ok, %{"some" => %{"juicy_unknown_key_1" => 1}}}
+    assert Juicy.parse(~s({"thing": 1, "some": 2}), keys: {:intern, [:thing]}) ==
+      {:ok, %{:thing => 1, "some" => 2}}
+    assert Juicy.parse(~s({"juicy_new_atom_key": 1, "ö": 2}), keys: :atoms) ==
+      {:ok, %{juicy_new_atom_key: 1, "ö" => 2}}
+  end
+
+  test "ordered objects" do
+    input = ~s({"b": 1, "a": {"d": 2, "c": 3}, "b": 4})
+    assert Juicy.parse(input, objects: :ordered_lists) ==
+      {:ok, [{"b", 1}, {"a", [{"d", 2}, {"c", 3}]}, {"b", 4}]}
+    assert Juicy.parse(input, objects: :proplists) ==
+      {:ok, [{"b", 4}, {"a", [{"c", 3}, {"d", 2}]}, {"b", 1}]}
+    assert Juicy.parse(input, objects: :ordered_lists, duplicate_keys: :first) ==
+      {:ok, [{"b", 1}, {"a", [{"d", 2}, {"c", 3}]}]}
+    assert {:error, %Juicy.Error{reason: :duplicate_key, path: "/b"}} =
+      Juicy.parse(input, objects: :ordered_lists, duplicate_keys: :error)
+    assert Juicy.parse("{}", objects: :ordered_lists) == {:ok, []}
+
+    spec = {:map, [], {:map, [objects: :ordered_lists], {:any, []}}}
+    assert Juicy.parse_spec(~s({"a": {"y": {"z": 1}, "x": 2}}), spec) ==
+      {:ok, %{"a" => [{"y", [{"z", 1}]}, {"x", 2}]}}
+  end
+
+  test "limits" do
+    assert {:error, %Juicy.Error{reason: :max_depth_exceeded}} =
+      Juicy.parse("[[[1]]]", max_depth: 2)
+    assert {:ok, _} = Juicy.parse("[[1]]", max_depth: 2)
+
+    assert {:error, %Juicy.Error{reason: :max_string_bytes_exceeded}} =
+      Juicy.parse(~s(["abcd"]), max_string_bytes: 3)
+    assert {:error, %Juicy.Error{reason: :max_string_bytes_exceeded}} =
+      Juicy.parse(~s({"a\\nbc": 1}), max_string_bytes: 3)
+    assert {:ok, _} = Juicy.parse(~s(["abc"]), max_string_bytes: 3)
+
+    assert {:error, %Juicy.Error{reason: :max_number_digits_exceeded, path: "/1"}} =
+      Juicy.parse("[1, 1.5e10]", max_number_digits: 3)
+
+    assert {:error, %Juicy.Error{reason: :max_keys_per_object_exceeded}} =
+      Juicy.parse(~s({"a": 1, "b": 2, "c": 3}), max_keys_per_object: 2)
+    assert {:error, %Juicy.Error{reason: :max_keys_per_object_exceeded}} =
+      Juicy.parse(~s({"a": 1, "a": 2, "a": 3}), max_keys_per_object: 2)
+    assert {:error, %Juicy.Error{reason: :max_keys_per_object_exceeded}} =
+      Juicy.parse(~s({"a": 1, "a": 2, "a": 3}), max_keys_per_object: 2, objects: :ordered_lists)
+
+    assert {:error, %Juicy.Error{reason: :max_total_bytes_exceeded, offset: 4}} =
+      Juicy.parse("[1, 2]", max_total_bytes: 4)
+    assert {:ok, _} = Juicy.parse("[1, 2]", max_total_bytes: 6)
+  end
+
+  test "limits with spec and streams" do
+    spec = {:array, [], {:any, []}}
+    assert {:error, %Juicy.Error{reason: :max_depth_exceeded}} =
+      Juicy.parse_spec("[[[1]]]", spec, max_depth: 2)
+
+    out = Juicy.parse_stream(["[1, ", "[[2]]]"], spec, max_depth: 2) |> Enum.into([])
+    assert [{:error, %Juicy.Error{reason: :max_depth_exceeded}}] = out
+  end
+
+  test "duplicate keys with spec" do
+    input = ~s({"a": {"c": 2, "c": 3}, "b": {"c": 2, "c": 3}})
+    spec = {:map, [], {:map, [duplicate_keys: :first], {:any, []}}}
+    assert Juicy.parse_spec(input, spec) ==
+      {:ok, %{"a" => %{"c" => 2}, "b" => %{"c" => 2}}}
+
+    spec = {:map, [duplicate_keys: :error], {:any, []}}
+    assert {:error, %Juicy.Error{reason: :duplicate_key, path: "/a/c"}} =
+      Juicy.parse_spec(input, spec)
+  end
+
   test "match spec validation" do
-    assert :ok == Juicy.validate_spec({:map, [], {:any, []}})
-    assert :ok == Juicy.validate_spec({:map, [], {:any, [stream: true]}})
-    assert :ok == Juicy.validate_spec({:map_keys, [], %{"a" => {:any, []}}})
-    assert :ok == Juicy.validate_spec({:map, [atom_keys: [:some]], {:any, []}})
+    assert {:ok, _} = Juicy.validate_spec({:map, [], {:any, []}})
+    assert {:ok, _} = Juicy.validate_spec({:map, [], {:any, [stream: true]}})
+    assert {:ok, _} = Juicy.validate_spec({:map_keys, [], %{"a" => {:any, []}}})
+    assert {:ok, _} = Juicy.validate_spec({:map, [atom_keys: [:some]], {:any, []}})
+    assert {:ok, _} = Juicy.validate_spec({:tuple, [as_tuple: true], [{:any, []}]})
+    assert {:ok, _} = Juicy.validate_spec({:enum, [default: nil], [:a, "b"]})
+    assert {:ok, _} = Juicy.validate_spec({:static, [value: {1, 2}]})
+    assert {:ok, _} = Juicy.validate_spec({:value, [raw: true]})
+    assert {:ok, _} = Juicy.validate_spec({:tagged_map, [key_name: "t", default: {:node, {:any, []}}], %{}})
+    assert :error == Juicy.validate_spec({:tagged_map, [], %{"a" => {:any, []}}})
+    assert {:ok, _} = Juicy.validate_spec({:type, [], %{string: {:any, []}, null: {:any, []}}})
+    assert :error == Juicy.validate_spec({:type, [], %{text: {:any, []}}})
     assert :error == Juicy.validate_spec(nil)
     assert :error == Juicy.validate_spec({:abc, [], {:any, []}})
     assert :error == Juicy.validate_spec({:map_keys, [], %{0 => {:any, []}}})
+    assert {:ok, _} = Juicy.validate_spec({:map_keys, [default: {:any, []}], %{"a" => {:any, []}}})
+    assert {:ok, _} = Juicy.validate_spec({:any, [min: 1, max: 2.5, integer: true, format: :email]})
+    assert {:ok, _} = Juicy.validate_spec({:map, [ignore_unknown: true], {:ignore, []}})
+    assert :error == Juicy.validate_spec({:any, [pattern: "("]})
+    assert :error == Juicy.validate_spec({:any, [format: :phone]})
+  end
+
+  test "compiled specs" do
+    spec = {:map, [atom_keys: [:a]], {:array, [], {:any, [stream: true]}}}
+    {:ok, compiled} = Juicy.compile_spec(spec)
+    input = ~s({"a": [1.5, {"b": 1, "b": 2}]})
+
+    assert Juicy.parse_spec(input, compiled) == Juicy.parse_spec(input, spec)
+    opts = [floats: :string, duplicate_keys: :first]
+    assert Juicy.parse_spec(input, compiled, opts) == Juicy.parse_spec(input, spec, opts)
+    assert Juicy.parse_spec(input, compiled, opts) ==
+      {:ok, %{a: ["1.5", %{"b" => 1}]}}
+
+    assert Juicy.parse_stream([input], compiled) |> Enum.into([]) ==
+      Juicy.parse_stream([input], spec) |> Enum.into([])
   end
 
   test "basic stream" do
@@ -67,6 +215,53 @@ defmodule JuicyTest do
     ]
   end
 
+  test "collecting streamed values" do
+    input = [~s({"rows": [1, 2], "more": [3, ), ~s(4, 5]})]
+    rows = {:array, [], {:any, [stream: true]}}
+
+    spec = {:map, [stream: true], {:array, [stream_collect: :drop], {:any, [stream: true]}}}
+    out = Juicy.parse_stream(input, spec) |> Enum.into([])
+    assert length(out) == 7
+    assert Enum.take(out, -2) == [
+      {:yield, {[], %{"rows" => [], "more" => []}}},
+      :finished,
+    ]
+
+    spec = {:map, [stream: true], {:array, [stream_collect: :count], {:any, [stream: true]}}}
+    out = Juicy.parse_stream(input, spec) |> Enum.into([])
+    assert Enum.take(out, -2) == [
+      {:yield, {[], %{"rows" => 2, "more" => 3}}},
+      :finished,
+    ]
+
+    spec = {:map, [stream: true, stream_collect: :drop], rows}
+    assert Juicy.parse_stream(input, spec) |> Enum.into([]) |> Enum.take(-2) == [
+      {:yield, {[], %{"rows" => [:streamed, :streamed], "more" => [:streamed, :streamed, :streamed]}}},
+      :finished,
+    ]
+
+    assert {:ok, _} = Juicy.validate_spec({:array, [stream_collect: :count], rows})
+    assert :error == Juicy.validate_spec({:array, [stream_collect: :all], rows})
+
+    spec = {:tuple, [stream: true, stream_collect: :drop, as_tuple: true],
+            [{:any, [stream: true]}, {:any, []}]}
+    assert Juicy.parse_stream([~s([1, 2])], spec) |> Enum.into([]) == [
+      {:yield, {[0], 1}},
+      {:yield, {[], {2}}},
+      :finished,
+    ]
+
+    spec = {:map_keys, [stream: true, stream_collect: :count, required: ["a", "b"]],
+            %{"a" => {:any, [stream: true]}, "b" => {:any, []}}}
+    assert Juicy.parse_stream([~s({"a": 1, "b": 2})], spec) |> Enum.into([]) == [
+      {:yield, {["a"], 1}},
+      {:yield, {[], 1}},
+      :finished,
+    ]
+    assert [{:yield, {["a"], 1}}, {:error, %Juicy.Error{reason: :missing_keys}}] =
+      Juicy.parse_stream([~s({"a": 1})], spec) |> Enum.into([])
+  end
+
   test "early end of input stream" do
     input = ["{"]
     spec = {:any, []}
@@ -75,6 +270,73 @@ defmodule JuicyTest do
     assert out == [error: :early_eoi]
   end
 
+  test "line delimited stream" do
+    input = [~s({"id": 1}\n\n{"i), ~s(d": 2}  \r\n[3]\n4)]
+    assert Juicy.parse_lines(input) |> Enum.into([]) == [
+      {:line, 1, %{"id" => 1}},
+      {:line, 3, %{"id" => 2}},
+      {:line, 4, [3]},
+      {:line, 5, 4},
+      :finished,
+    ]
+
+    spec = {:map, [atom_keys: [:id]], {:any, [min: 2]}}
+    input = [~s({"id": 1}\n{"id": 2} x\n{"id": ), ~s(3}\n{"id"\n{"id": 5}\n)]
+    assert [{:error, %Juicy.Error{reason: :constraint_violations, line: 1}}] =
+      Juicy.parse_lines(input, spec) |> Enum.into([])
+
+    out = Juicy.parse_lines(input, spec, skip_invalid: true) |> Enum.into([])
+    assert [
+      {:error, %Juicy.Error{reason: :constraint_violations, line: 1}},
+      {:error, %Juicy.Error{reason: :expected_newline, line: 2}},
+      {:line, 3, %{id: 3}},
+      {:error, %Juicy.Error{line: 4}},
+      {:line, 5, %{id: 5}},
+      :finished,
+    ] = out
+
+    assert [{:line, 1, []}, {:error, %Juicy.Error{reason: :truncated, line: 2}}] =
+      Juicy.parse_lines(["[]\n{"]) |> Enum.into([])
+
+    opts = [max_total_bytes: 6, skip_invalid: true]
+    assert [{:line, 1, [1]}, {:error, %Juicy.Error{reason: :max_total_bytes_exceeded, line: 2}}] =
+      Juicy.parse_lines(["[1]\n[2]\n[3]\n"], {:any, []}, opts) |> Enum.into([])
+  end
+
+  test "concatenated documents" do
+    assert Juicy.parse_many(~s({"a": 1}{"b": 2}\n[3] 4 "x"null)) ==
+      {:ok, [%{"a" => 1}, %{"b" => 2}, [3], 4, "x", nil]}
+    assert Juicy.parse_many("") == {:ok, []}
+    assert {:error, %Juicy.Error{reason: :truncated}} = Juicy.parse_many(~s({}{"a":))
+
+    spec = {:array, [stream_collect: :count], {:any, [stream: true]}}
+    assert Juicy.parse_many_stream(["[1", "][2]"], spec) |> Enum.into([]) == [
+      {:yield, {[0], 1}},
+      {:document, 0, 1},
+      {:yield, {[0], 2}},
+      {:document, 1, 1},
+      :finished,
+    ]
+  end
+
+  test "json text sequences" do
+    input = "\x1E{\"a\": 1}\n\x1E\x1E [2]\n"
+    assert Juicy.parse_many(input, format: :json_seq) == {:ok, [%{"a" => 1}, [2]]}
+    assert {:error, %Juicy.Error{reason: :truncated}} =
+      Juicy.parse_many(input <> "\x1E3", format: :json_seq)
+    assert {:error, %Juicy.Error{reason: :expected_separator}} =
+      Juicy.parse_many("[]\n", format: :json_seq)
+
+    input = ["\x1E{\"a\": ", "\x1E[1]\n\x1E2\n"]
+    out = Juicy.parse_many_stream(input, {:any, []}, format: :json_seq, skip_invalid: true)
+    assert [
+      {:error, %Juicy.Error{reason: :truncated}},
+      {:document, 1, [1]},
+      {:document, 2, 2},
+      :finished,
+    ] = Enum.into(out, [])
+  end
+
   test "json parsing with simple spec" do
     input = ~s({"a": 0, "b": 1})
     spec = {:map, [atom_keys: [:a, :b]], {:any, []}}
@@ -94,4 +356,406 @@ defmodule JuicyTest do
                     ]}
   end
 
+  test "keys without atoms" do
+    input = ~s({"a": 1, "b": {"c": 2}, "d": 3})
+    spec = {:map, [atom_keys: [:a, :d], ignore_non_atoms: true], {:any, []}}
+    assert Juicy.parse_spec(input, spec) == {:ok, %{a: 1, d: 3}}
+
+    spec = {:map, [atom_keys: [:a, :d], ignore_non_atoms: true, stream: true], {:any, []}}
+    assert [{:yield, {[], %{a: 1, d: 3}}}, :finished] =
+      Juicy.parse_stream([input], spec) |> Enum.into([])
+
+    spec = {:array, [], {:map, [atom_keys: [:a, :d], reject_non_atoms: true], {:any, []}}}
+    assert {:error, %Juicy.Error{reason: :unknown_key, path: "/0/b"}} =
+      Juicy.parse_spec("[#{input}]", spec)
+  end
+
+  test "ignored values" do
+    input = ~s({"id": 1, "payload": {"big": [1, 2, {"x": "y"}]}, "tags": ["a", "b"], "n": null})
+
+    spec = {:map_keys, [], %{"payload" => {:ignore, []}, "tags" => {:array, [], {:ignore, []}}}}
+    assert Juicy.parse_spec(input, spec) == {:ok, %{"id" => 1, "tags" => [nil, nil], "n" => nil}}
+
+    spec = {:map_keys, [ignore_unknown: true, stream: true], %{"id" => {:any, []}, "n" => {:any, []}}}
+    assert Juicy.parse_spec(input, spec) == {:ok, %{"id" => 1, "n" => nil}}
+    assert [{:yield, {[], %{"id" => 1, "n" => nil}}}, :finished] =
+      Juicy.parse_stream([input], spec) |> Enum.into([])
+
+    assert {:ok, nil} = Juicy.parse_spec(input, {:ignore, []})
+    assert {:error, %Juicy.Error{path: "/payload/big/2"}} =
+      Juicy.parse_spec(~s({"payload": {"big": [1, 2, }}), spec)
+  end
+
+  test "tuple spec nodes" do
+    spec = {:tuple, [], [{:any, []}, {:map, [atom_keys: [:some]], {:any, []}}]}
+    assert Juicy.parse_spec(~s([1, {"some": 2}]), spec) ==
+      {:ok, [1, %{some: 2}]}
+
+    spec = {:map, [], {:tuple, [as_tuple: true], [{:any, []}, {:any, []}]}}
+    assert Juicy.parse_spec(~s({"point": [1.5, 2]}), spec) == {:ok, %{"point" => {1.5, 2}}}
+
+    assert {:error, %Juicy.Error{reason: :wrong_arity, path: "/point"}} =
+      Juicy.parse_spec(~s({"point": [1, 2, 3]}), spec)
+    assert {:error, %Juicy.Error{reason: :wrong_arity, path: "/point"}} =
+      Juicy.parse_spec(~s({"point": []}), spec)
+  end
+
+  test "enum spec nodes" do
+    spec = {:map, [atom_keys: [:status]], {:enum, [], ["active", "suspended"]}}
+    assert Juicy.parse_spec(~s({"status": "active"}), spec) == {:ok, %{status: :active}}
+
+    assert {:error, %Juicy.Error{reason: :invalid_enum_value, path: "/status"}} =
+      Juicy.parse_spec(~s({"status": "gone"}), spec)
+    assert [{:error, %Juicy.Error{reason: :invalid_enum_value, path: "/status"}}] =
+      Juicy.parse_stream([~s({"status": "gone"})], spec) |> Enum.into([])
+
+    spec = {:array, [], {:enum, [default: :unknown], %{"a" => :first, "b" => :second}}}
+    assert Juicy.parse_spec(~s(["b", "a", "c"]), spec) == {:ok, [:second, :first, :unknown]}
+    assert Juicy.parse_spec(~s([1, null, {"a": "b"}, ["a"]]), spec) ==
+      {:ok, [:unknown, :unknown, :unknown, :unknown]}
+
+    spec = {:map, [], {:enum, [], ["a"]}}
+    assert {:error, %Juicy.Error{reason: :invalid_enum_value, path: "/x"}} =
+      Juicy.parse_spec(~s({"x": 5}), spec)
+    assert {:error, %Juicy.Error{reason: :invalid_enum_value, path: "/x"}} =
+      Juicy.parse_spec(~s({"x": ["a"]}), spec)
+  end
+
+  test "static spec nodes" do
+    spec = {:map, [], {:static, [value: "[redacted]"]}}
+    assert Juicy.parse_spec(~s({"password": "hunter2", "pin": [1, 2]}), spec) ==
+      {:ok, %{"password" => "[redacted]", "pin" => "[redacted]"}}
+
+    assert Juicy.parse_spec(~s([1, {"a": 2}]), {:array, [], {:static, []}}) == {:ok, [nil, nil]}
+  end
+
+  test "value spec nodes" do
+    input = ~s({"a": {"b": [1, 2]}, "c": "x\\"y", "d": -1.5e3, "e": true})
+
+    spec = {:map, [], {:value, []}}
+    assert Juicy.parse_spec(input, spec) ==
+      {:ok, %{"a" => %{"b" => [1, 2]}, "c" => "x\"y", "d" => -1.5e3, "e" => true}}
+
+    expected = %{
+      "a" => ~s({"b": [1, 2]}),
+      "c" => ~s("x\\"y"),
+      "d" => "-1.5e3",
+      "e" => "true",
+    }
+    spec = {:map, [], {:value, [raw: true]}}
+    assert Juicy.parse_spec(input, spec) == {:ok, expected}
+
+    chunks = for <<byte <- input>>, do: <<byte>>
+    assert [{:yield, {[], ^expected}}, :finished] =
+      Juicy.parse_stream(chunks, {:map, [stream: true], {:value, [raw: true]}}) |> Enum.into([])
+
+    input = ~s([\n  {"a": [ 1 ]},\n\t[ [],{} ] ,\r\n 7, -2.5e1, null,false])
+    expected = [~s({"a": [ 1 ]}), "[ [],{} ]", "7", "-2.5e1", "null", "false"]
+    spec = {:array, [], {:value, [raw: true]}}
+    assert Juicy.parse_spec(input, spec) == {:ok, expected}
+
+    chunks = for <<byte <- input>>, do: <<byte>>
+    assert [{:yield, {[], ^expected}}, :finished] =
+      Juicy.parse_stream(chunks, {:array, [stream: true], {:value, [raw: true]}}) |> Enum.into([])
+    assert Juicy.parse_spec("[1, 23]", spec) == {:ok, ["1", "23"]}
+  end
+
+  test "tagged map spec nodes" do
+    click = {:map, [atom_keys: [:type, :x]], {:any, []}}
+    key = {:map, [atom_keys: [:type, :key]], {:any, []}}
+    spec = {:array, [], {:tagged_map, [key_name: "type"], %{"click" => click, "key" => key}}}
+
+    input = ~s([{"key": "a", "type": "key"}, {"type": "click", "x": 1}])
+    assert Juicy.parse_spec(input, spec) ==
+      {:ok, [%{key: "a", type: "key"}, %{type: "click", x: 1}]}
+
+    assert {:error, %Juicy.Error{reason: :unknown_tag, path: "/0"}} =
+      Juicy.parse_spec(~s([{"type": "scroll"}]), spec)
+    assert {:error, %Juicy.Error{reason: :missing_tag, path: "/0"}} =
+      Juicy.parse_spec(~s([{"key": "a"}]), spec)
+
+    spec = {:array, [], {:tagged_map, [key_name: "type", default: :ignore], %{"key" => key}}}
+    assert Juicy.parse_spec(~s([{"type": "scroll", "by": 2}]), spec) ==
+      {:ok, [%{"type" => "scroll", "by" => 2}]}
+
+    spec = {:tagged_map, [key_name: "type", default: {:node, key}, stream: true], %{}}
+    assert [{:yield, {[], %{type: "scroll"}}}, :finished] =
+      Juicy.parse_stream([~s({"ty), ~s(pe": "scroll"})], spec) |> Enum.into([])
+
+    streamed = {:map, [], {:any, [stream: true]}}
+    spec = {:array, [], {:tagged_map, [key_name: "type"], %{"key" => key, "list" => streamed}}}
+    assert [{:error, %Juicy.Error{reason: :unsupported_spec, path: "/1"}}] =
+      Juicy.parse_stream([~s([{"type": "key"}, {"type": "list"}])], spec) |> Enum.into([])
+
+    strict = {:map, [atom_keys: [:type], reject_non_atoms: true], {:any, []}}
+    spec = {:array, [], {:tagged_map, [key_name: "type"], %{"strict" => strict}}}
+    input = ~s([\n  {"type": "strict",\n   "other": 1}\n])
+    assert {:error, %Juicy.Error{reason: :unknown_key, line: 3}} = Juicy.parse_spec(input, spec)
+    chunks = for <<byte <- input>>, do: <<byte>>
+    assert [{:error, %Juicy.Error{reason: :unknown_key, line: 3}}] =
+      Juicy.parse_stream(chunks, spec) |> Enum.into([])
+  end
+
+  test "type spec nodes" do
+    id = {:type, [], %{string: {:any, []}, number: {:static, [value: :numeric]}}}
+    spec = {:map, [atom_keys: [:id]], id}
+    assert Juicy.parse_spec(~s({"id": "a1"}), spec) == {:ok, %{id: "a1"}}
+    assert Juicy.parse_spec(~s({"id": 12}), spec) == {:ok, %{id: :numeric}}
+
+    spec = {:array, [], {:type, [], %{map: {:map, [atom_keys: [:a]], {:any, []}}, null: {:any, []}}}}
+    assert Juicy.parse_spec(~s([{"a": 1}, null, true]), spec) == {:ok, [%{a: 1}, nil, true]}
+  end
+
+  test "top level scalars with spec" do
+    assert Juicy.parse_spec("12", {:any, []}) == {:ok, 12}
+    assert Juicy.parse_spec(~s("woo"), {:any, []}) == {:ok, "woo"}
+  end
+
+  test "map_keys spec nodes" do
+    spec = {:map_keys, [atom_keys: [:some, :thing], struct_atom: JuicyTest.TestStruct],
+            %{"some" => {:enum, [], ["a", "b"]}, "thing" => {:tuple, [as_tuple: true], [{:any, []}, {:any, []}]}}}
+    input = ~s({"some": "b", "thing": [1, 2]})
+    assert Juicy.parse_spec(input, spec) == {:ok, %JuicyTest.TestStruct{some: :b, thing: {1, 2}}}
+    assert [{:yield, {[], %JuicyTest.TestStruct{some: :b, thing: {1, 2}}}}, :finished] =
+      Juicy.parse_stream([input], put_elem(spec, 1, [stream: true] ++ elem(spec, 1))) |> Enum.into([])
+
+    spec = {:map_keys, [default: {:static, [value: :other]}], %{"a" => {:any, []}}}
+    assert Juicy.parse_spec(~s({"a": 1, "b": 2, "c": [3]}), spec) ==
+      {:ok, %{"a" => 1, "b" => :other, "c" => :other}}
+
+    spec = {:map_keys, [], %{"a" => {:enum, [], ["x"]}}}
+    assert Juicy.parse_spec(~s({"a": "x", "b": "y"}), spec) == {:ok, %{"a" => :x, "b" => "y"}}
+  end
+
+  test "required keys and defaults" do
+    spec = {:array, [], {:map, [atom_keys: [:some, :thing], struct_atom: JuicyTest.TestStruct,
+                                required: [:some], defaults: %{thing: 0}], {:any, []}}}
+    assert Juicy.parse_spec(~s([{"some": 1}, {"some": 2, "thing": 3}]), spec) ==
+      {:ok, [%JuicyTest.TestStruct{some: 1, thing: 0}, %JuicyTest.TestStruct{some: 2, thing: 3}]}
+
+    spec = {:map, [], {:map, [required: ["a", "b", "c"]], {:any, []}}}
+    assert {:error, error} = Juicy.parse_spec(~s({"x": {"b": 1}}), spec)
+    assert %Juicy.Error{reason: :missing_keys, path: "/x"} = error
+    assert [%{reason: :missing_key, path: "/x/a"}, %{reason: :missing_key, path: "/x/c"}] =
+      error.details
+
+    spec = {:map_keys, [required: ["a"], defaults: %{"b" => 0}, ignore_unknown: true],
+            %{"a" => {:ignore, []}}}
+    assert Juicy.parse_spec(~s({"a": 1, "b": 2}), spec) == {:ok, %{}}
+    assert Juicy.parse_spec(~s({"a": 1}), spec) == {:ok, %{"b" => 0}}
+  end
+
+  test "scalar constraints" do
+    spec = {:map_keys, [], %{
+               "name" => {:any, [min_length: 2, max_length: 5, pattern: "^[a-z]+$"]},
+               "id" => {:any, [format: :uuid]},
+               "at" => {:any, [format: :date_time]},
+               "age" => {:any, [min: 0, max: 150, integer: true]},
+             }}
+    input = ~s({"name": "ana", "id": "6ba7b810-9dad-11d1-80b4-00c04fd430c8",
+                "at": "2024-02-29T13:05:00.25+01:00", "age": 31})
+    assert {:ok, %{"name" => "ana", "age" => 31}} = Juicy.parse_spec(input, spec)
+
+    input = ~s({"name": "Anabella", "id": "6ba7b810", "at": "2023-02-29T13:05:00Z", "age": 1.5e3})
+    assert {:error, error} = Juicy.parse_spec(input, spec)
+    assert %Juicy.Error{reason: :constraint_violations, path: ""} = error
+    assert [
+      %{reason: :too_long, path: "/name"},
+      %{reason: :pattern_mismatch, path: "/name"},
+      %{reason: :invalid_format, path: "/id"},
+      %{reason: :invalid_format, path: "/at"},
+      %{reason: :too_large, path: "/age"},
+    ] = error.details
+
+    spec = {:array, [], {:any, [format: :email, stream: true]}}
+    out = Juicy.parse_stream([~s(["a@b.c", "a@b"])], spec) |> Enum.into([])
+    assert [{:yield, {[0], "a@b.c"}}, {:yield, {[1], "a@b"}}, {:error, error}] = out
+    assert [%{reason: :invalid_format, path: "/1"}] = error.details
+  end
+
+  test "json schema" do
+    {:ok, schema} = Juicy.parse(~s({
+      "$schema": "https://json-schema.org/draft/2020-12/schema",
+      "type": "object",
+      "required": ["id", "shapes"],
+      "properties": {
+        "id": {"type": "string", "format": "uuid"},
+        "shapes": {
+          "type": "array",
+          "items": {
+            "oneOf": [
+              {"properties": {"kind": {"const": "circle"}, "r": {"type": "number", "minimum": 0}}},
+              {"properties": {"kind": {"const": "square"}, "side": {"type": "integer"}}}
+            ],
+            "discriminator": {"propertyName": "kind"}
+          }
+        },
+        "color": {"enum": ["red", "green", null]}
+      }
+    }))
+    assert {:ok, spec} = Juicy.Spec.from_json_schema(schema)
+    assert is_reference(spec)
+
+    input = ~s({"id": "6ba7b810-9dad-11d1-80b4-00c04fd430c8", "color": null,
+                "shapes": [{"kind": "circle", "r": 1.5}, {"side": 2, "kind": "square"}]})
+    assert {:ok, %{"color" => nil, "shapes" => [%{"r" => 1.5}, %{"side" => 2}]}} =
+      Juicy.parse_spec(input, spec)
+
+    input = ~s({"id": "x", "color": "blue", "shapes": [{"kind": "square", "side": 2.5}]})
+    assert {:error, %Juicy.Error{reason: :constraint_violations} = error} =
+      Juicy.parse_spec(input, spec)
+    assert [
+      %{reason: :invalid_enum_value, path: "/color"},
+      %{reason: :invalid_format, path: "/id"},
+      %{reason: :not_integer, path: "/shapes/0/side"},
+    ] = Enum.sort_by(error.details, & &1.path)
+
+    assert {:error, %Juicy.Error{reason: :missing_keys}} = Juicy.parse_spec(~s({"id": "x"}), spec)
+    assert {:error, %Juicy.Error{reason: :constraint_violations} = error} =
+      Juicy.parse_spec(~s({"id": 5, "shapes": 1}), spec)
+    assert [
+      %{reason: :type_mismatch, path: "/id"},
+      %{reason: :type_mismatch, path: "/shapes"},
+    ] = Enum.sort_by(error.details, & &1.path)
+
+    {:ok, items_spec} = Juicy.Spec.from_json_schema(%{"items" => %{"enum" => ["a"]}})
+    assert {:error, %Juicy.Error{reason: :constraint_violations} = error} =
+      Juicy.parse_spec(~s(["a", 5, ["a"], {}]), items_spec)
+    assert [
+      %{reason: :type_mismatch, path: "/1"},
+      %{reason: :type_mismatch, path: "/2"},
+      %{reason: :type_mismatch, path: "/3"},
+    ] = Enum.sort_by(error.details, & &1.path)
+    assert {:error, %Juicy.Error{reason: :unknown_tag, path: "/shapes/0"}} =
+      Juicy.parse_spec(~s({"id": "x", "shapes": [{"kind": "oval"}]}), spec)
+
+    assert {:error, {:unsupported_keyword, "$ref", "/properties/a/$ref"}} =
+      Juicy.Spec.from_json_schema(%{"properties" => %{"a" => %{"$ref" => "#/$defs/a"}}})
+    assert {:error, {:unsupported_keyword, "oneOf", "/oneOf"}} =
+      Juicy.Spec.from_json_schema(%{"oneOf" => [true, false]})
+    assert {:error, {:invalid_keyword, "type", "/items/type"}} =
+      Juicy.Spec.from_json_schema(%{"items" => %{"type" => "text"}})
+  end
+
+  test "errors in streams" do
+    out = Juicy.parse_stream(["[1,", "\n  x]"], {:any, []}) |> Enum.into([])
+    assert [{:error, %Juicy.Error{line: 2, column: 3, path: "/1"}}] = out
+
+    assert [{:error, %Juicy.Error{reason: :truncated}}] =
+      Juicy.parse_stream(["[1, ", "2"], {:any, []}) |> Enum.into([])
+  end
+
+  test "errors after a parse is resumed" do
+    input = "[" <> String.duplicate("1, ", 20_000) <> "[[1]]]"
+    spec = {:array, [], {:any, []}}
+    assert {:error, %Juicy.Error{reason: :max_depth_exceeded}} =
+      Juicy.parse_spec(input, spec, max_depth: 2)
+    assert [{:error, %Juicy.Error{reason: :max_depth_exceeded}}] =
+      Juicy.parse_stream([input], spec, max_depth: 2) |> Enum.into([])
+
+    {:iter, [_], encoder} = Juicy.Native.encode_init(Map.new(1..20_000, &{&1, &1}), [])
+    assert_raise ArgumentError, fn -> Juicy.Native.spec_parse_iter({"[]", [], encoder}) end
+    assert_raise ArgumentError, fn -> Juicy.Native.stream_parse_iter([], {[], encoder}) end
+  end
+
+  test "spec and stream parses agree" do
+    spec = {:map_keys, [stream: true, required: ["id"], defaults: %{"tags" => []}], %{
+               "id" => {:any, [integer: true]},
+               "kind" => {:enum, [], ["a", "b"]},
+               "point" => {:tuple, [as_tuple: true], [{:any, []}, {:any, []}]},
+               "tags" => {:array, [], {:any, [max_length: 3]}},
+             }}
+    stream = fn input ->
+      case Juicy.parse_stream([input], spec) |> Enum.into([]) do
+        [{:yield, {[], value}}, :finished] -> {:ok, value}
+        [{:error, error}] -> {:error, error}
+      end
+    end
+
+    inputs = [
+      ~s({"id": 1, "kind": "a", "point": [1, 2.5], "tags": ["x"]}),
+      ~s({"id": 2, "other": {"deep": [null]}}),
+      ~s({"kind": "b"}),
+      ~s({"id": 1.5, "tags": ["long"]}),
+      ~s({"id": 1, "kind": "c"}),
+      ~s({"id": 1, "point": [1]}),
+      ~s({"id": 1, "tags": ["x", }),
+    ]
+    for input <- inputs do
+      assert Juicy.parse_spec(input, spec) == stream.(input)
+    end
+  end
+
+  test "encoding basic values" do
+    assert Juicy.encode(%{"a" => [1, -2.5, true, false, nil]}) == {:ok, ~s({"a":[1,-2.5,true,false,null]})}
+    assert Juicy.encode([]) == {:ok, "[]"}
+    assert Juicy.encode(%{}) == {:ok, "{}"}
+    assert Juicy.encode(:woo) == {:ok, ~s("woo")}
+    assert Juicy.encode(%{woo: 1}) == {:ok, ~s({"woo":1})}
+    struct = %JuicyTest.TestStruct{some: 1}
+    assert Juicy.encode(struct) == {:error, {:unsupported_type, struct}}
+    assert Juicy.encode([%{"a" => struct}]) == {:error, {:unsupported_type, struct}}
+  end
+
+  test "encoding strings" do
+    assert Juicy.encode("\"\\\n\u0001\u00E5") == {:ok, ~s("\\"\\\\\\n\\u0001\u00E5")}
+    assert Juicy.encode(<<255>>) == {:error, {:invalid_string, <<255>>}}
+  end
+
+  test "encoding large integers" do
+    assert Juicy.encode([9999999999999999999999999999999999999999, -9999999999999999999999]) ==
+      {:ok, "[9999999999999999999999999999999999999999,-9999999999999999999999]"}
+  end
+
+  test "encoding large inputs round trips" do
+    input = Enum.map(1..20_000, fn n -> %{"n" => n, "s" => "item"} end)
+    {:ok, json} = Juicy.encode(input)
+    assert p(json) == {:ok, input}
+
+    {:iter, [_], res} = Juicy.Native.encode_init(Map.new(1..20_000, &{&1, &1}), [])
+    assert_raise ArgumentError, fn ->
+      Juicy.Native.encode_iter([{:object, [:bad], false}], res)
+    end
+
+    big = Map.new(1..50_000, &{&1, &1})
+    assert {:iter, [{:array, [], false}, {:value, ^big, true}], _} =
+      Juicy.Native.encode_init(["x", big], [])
+    {:ok, json} = Juicy.encode(["x", big])
+    assert p(json) == {:ok, ["x", Map.new(big, fn {k, v} -> {Integer.to_string(k), v} end)]}
+  end
+
+  test "pretty encoding" do
+    input = %{"b" => [1, %{}], "a" => []}
+    expected = """
+    {
+      "a": [],
+      "b": [
+        1,
+        {}
+      ]
+    }
+    """
+    assert Juicy.encode(input, pretty: true) == {:ok, String.trim_trailing(expected)}
+
+    assert Juicy.encode(input, pretty: true, indent: "\t", after_colon: "") ==
+      {:ok, "{\n\t\"a\":[],\n\t\"b\":[\n\t\t1,\n\t\t{}\n\t]\n}"}
+  end
+
+  test "canonical encoding" do
+    input = %{"\u20ac" => 1, "\r" => 2, "\u{1F600}" => 3, "1" => 4, "\u00f6" => 5}
+    assert Juicy.encode(input, canonical: true) ==
+      {:ok, ~s({"\\r":2,"1":4,"\u00f6":5,"\u20ac":1,"\u{1F600}":3})}
+
+    assert Juicy.encode([1.0, 1.0e21, 1.0e-7, 0.000001, 4.5, 9007199254740991], canonical: true) ==
+      {:ok, "[1,1e+21,1e-7,0.000001,4.5,9007199254740991]"}
+
+    assert Juicy.encode([9007199254740992], canonical: true) ==
+      {:error, {:integer_out_of_range, 9007199254740992}}
+    assert Juicy.encode([-9999999999999999999999], canonical: true) ==
+      {:error, {:integer_out_of_range, -9999999999999999999999}}
+    assert Juicy.encode([9999999999999999999999]) == {:ok, "[9999999999999999999999]"}
+
+    assert Juicy.encode(%{:a => 1, "a" => 2}, canonical: true) == {:error, {:duplicate_key, "a"}}
+  end
+
 end